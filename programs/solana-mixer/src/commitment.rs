@@ -0,0 +1,74 @@
+use anchor_lang::prelude::*;
+
+use crate::nullifier::{create_marker, RentPayer};
+use crate::{CommitmentRecord, ErrorCode, COMMITMENT_SEED, ZERO_HASHES};
+
+/// BN254 scalar field modulus r, little endian like the commitments
+const BN254_SCALAR_MODULUS: [u8; 32] = [
+    1, 0, 0, 240, 147, 245, 225, 67, 145, 112, 185, 121, 72, 232, 51, 40, 93, 88, 129, 129, 182,
+    69, 80, 184, 41, 160, 49, 225, 114, 78, 100, 48,
+];
+
+/// Reject commitments no circuit can open and the values the tree uses for
+/// empty leaves, which would make the note unspendable and confuse indexers
+pub fn check_commitment(commitment: &[u8; 32]) -> Result<()> {
+    // compare from the most significant byte down
+    require!(
+        commitment
+            .iter()
+            .rev()
+            .lt(BN254_SCALAR_MODULUS.iter().rev()),
+        ErrorCode::CommitmentNotInField
+    );
+    require!(
+        *commitment != [0u8; 32] && *commitment != ZERO_HASHES[0],
+        ErrorCode::ReservedCommitment
+    );
+    Ok(())
+}
+
+/// Create a `[COMMITMENT_SEED, pool, commitment]` record per commitment, paid
+/// by the depositor. `records` are the deposit's remaining accounts and `bumps`
+/// their PDA bumps, both in the order of `commitments`; taking the bumps from
+/// the client saves a `find_program_address` per commitment. A commitment seen
+/// before, in this batch or an earlier deposit, already has its record and
+/// fails with `DuplicateCommitment`.
+///
+/// Any bump that yields a valid address is accepted, so a depositor grinding a
+/// non-canonical one can get a second record for the same commitment. That
+/// only lets it pay `deposit_amount` into a leaf it cannot open; clients use
+/// the canonical bump.
+pub fn record_commitments<'info>(
+    pool: &Pubkey,
+    commitments: &[[u8; 32]],
+    records: &[AccountInfo<'info>],
+    bumps: &[u8],
+    depositor: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    require!(
+        records.len() == commitments.len() && bumps.len() == commitments.len(),
+        ErrorCode::InvalidCommitmentRecord
+    );
+    for ((commitment, record), bump) in commitments.iter().zip(records).zip(bumps) {
+        let seeds: &[&[u8]] = &[
+            COMMITMENT_SEED,
+            pool.as_ref(),
+            commitment.as_ref(),
+            &[*bump],
+        ];
+        let address = Pubkey::create_program_address(seeds, &crate::ID)
+            .map_err(|_| error!(ErrorCode::InvalidCommitmentRecord))?;
+        require_keys_eq!(*record.key, address, ErrorCode::InvalidCommitmentRecord);
+        require!(*record.owner != crate::ID, ErrorCode::DuplicateCommitment);
+
+        create_marker(
+            record,
+            RentPayer::Caller(depositor),
+            system_program,
+            seeds,
+            CommitmentRecord::DISCRIMINATOR,
+        )?;
+    }
+    Ok(())
+}
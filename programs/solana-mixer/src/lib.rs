@@ -1,12 +1,31 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{program::invoke, system_instruction};
 use anchor_lang::AccountDeserialize;
-use sp1_solana::verify_proof;
-use std::convert::TryInto;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+mod commitment;
+pub mod merkle_tree;
 mod nozeromerkle;
-use nozeromerkle::*;
+mod nullifier;
+pub mod public_inputs;
+mod recipient;
+mod relayer;
+mod token_pool;
+pub mod verifier;
+use commitment::*;
+pub use merkle_tree::MerkleTree;
+use merkle_tree::{check_tree_config, init_tree, TreeMut, TreeRef};
+use nullifier::*;
+pub use public_inputs::{
+    domain_tag, hash_splits, ProofKind, PublicInputs, TransactInputs, TransferInputs, WithdrawSplit,
+};
+use recipient::{check_rent_exempt, create_recipient_ata};
+use relayer::check_relayer_fee;
+pub use relayer::{RelayerEntry, MAX_RELAYER_URL_LEN, RELAYER_UNBONDING_PERIOD};
+use token_pool::*;
+pub use verifier::{CircomVerifyingKey, VerifierKind};
 
-pub const ZERO_HASHES: [[u8; 32]; TREE_DEPTH] = [
+pub const ZERO_HASHES: [[u8; 32]; DEFAULT_TREE_DEPTH as usize] = [
     [
         28, 225, 101, 203, 17, 36, 237, 58, 10, 148, 180, 226, 18, 170, 247, 232, 7, 159, 73, 178,
         251, 239, 145, 107, 194, 144, 197, 147, 253, 169, 9, 42,
@@ -91,211 +110,1799 @@ pub const ZERO_HASHES: [[u8; 32]; TREE_DEPTH] = [
 
 declare_id!("AQW933TrdFxE5q7982Vb57crHjZe3B7EZaHotdXnaQYQ");
 
-pub const TREE_DEPTH: usize = 20;
-pub const ROOT_HISTORY_SIZE: usize = 33;
+/// Tree shape of the original pools; `ZERO_HASHES` is precomputed up to this depth
+pub const DEFAULT_TREE_DEPTH: u8 = 20;
+pub const DEFAULT_ROOT_HISTORY_SIZE: u16 = 33;
+/// Bounds for the tree shape chosen at `initialize`. Together they keep a tree
+/// account under the 10 KiB a program may create through CPI.
+pub const MAX_TREE_DEPTH: u8 = 30;
+pub const MAX_ROOT_HISTORY_SIZE: u16 = 256;
 
-const MIXER_VKEY_HASH: &str = "0x00393c834697dedf3301f353f5f93f37c6f80df6a46db8004319bb4e582089bb";
-const GROTH16_VK_4_0_0_RC3_BYTES: &[u8] = &sp1_solana::GROTH16_VK_4_0_0_RC3_BYTES;
+/// SP1 mixer program vkey hash every new pool starts with
+/// (0x00393c834697dedf3301f353f5f93f37c6f80df6a46db8004319bb4e582089bb)
+pub const DEFAULT_VKEY_HASH: [u8; 32] = [
+    0, 57, 60, 131, 70, 151, 222, 223, 51, 1, 243, 83, 245, 249, 63, 55, 198, 248, 13, 246, 164,
+    109, 184, 0, 67, 25, 187, 78, 88, 32, 137, 187,
+];
+/// How many rotated-out vkey hashes can still be inside their grace window
+pub const RETIRED_VKEY_SLOTS: usize = 2;
+/// Longest grace window `rotate_vkey` accepts (30 days)
+pub const MAX_VKEY_GRACE_PERIOD: i64 = 30 * 24 * 60 * 60;
 pub const STATE_SEED: &[u8] = b"mixer_state";
+/// Mint recorded for pools that hold native SOL rather than an SPL token.
+pub const NATIVE_MINT: Pubkey = Pubkey::new_from_array([0u8; 32]);
+pub const VAULT_SEED: &[u8] = b"vault";
+pub const VERIFYING_KEY_SEED: &[u8] = b"verifying_key";
+pub const NULLIFIER_SEED: &[u8] = b"nullifier";
+pub const TREE_SEED: &[u8] = b"tree";
+pub const COMMITMENT_SEED: &[u8] = b"commitment";
+pub const TREASURY_SEED: &[u8] = b"treasury";
+pub const RELAYER_SEED: &[u8] = b"relayer";
+/// Ceiling on the protocol fee, flat part included, as a share of `deposit_amount` (5%)
+pub const MAX_PROTOCOL_FEE_BPS: u16 = 500;
+/// Most commitments one `deposit_batch` may carry; bounded by transaction size
+/// and the compute budget of hashing them in
+pub const MAX_DEPOSIT_BATCH: usize = 16;
+/// Largest `encrypted_note` a deposit may attach. The program never reads it;
+/// the client format in `tests::note` needs 112 bytes.
+pub const MAX_ENCRYPTED_NOTE_LEN: usize = 256;
+/// Most payees one `withdraw` may split the recipient's part between
+pub const MAX_WITHDRAW_SPLITS: usize = 8;
+/// Bounds `refund` in SOL pools: at most the rent-exempt minimum of an account
+/// this size, the nullifier account a withdrawal creates. That covers the
+/// transaction fees a relayer fronts without turning `refund` into a second
+/// relayer fee that `max_relayer_fee` does not cap.
+pub const MAX_REFUND_ACCOUNT_SPACE: usize = Nullifier::SPACE;
 
 #[program]
 
 pub mod solana_mixer {
     use super::*;
 
-    pub fn initialize(ctx: Context<Initialize>, deposit_amount: u64) -> Result<()> {
-        let state = &mut ctx.accounts.state;
-        state.bump = ctx.bumps.state;
-        state.administrator = *ctx.accounts.admin.key;
-        state.next_index = 0;
-        state.current_root_index = 0;
-        state.deposit_amount = deposit_amount;
-
-        state.filled_subtrees.copy_from_slice(&ZERO_HASHES);
-        let top = ZERO_HASHES[TREE_DEPTH - 1];
-        state.current_root = top;
-        for slot in state.root_history.iter_mut() {
-            *slot = top;
+    /// `cluster_genesis_hash` is the genesis hash of the cluster the program is
+    /// deployed on; it goes into the pool's domain tag so proofs cannot be
+    /// replayed against another deployment.
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        deposit_amount: u64,
+        cluster_genesis_hash: [u8; 32],
+        tree_depth: u8,
+        root_history_size: u16,
+    ) -> Result<()> {
+        check_tree_config(tree_depth, root_history_size)?;
+        let domain = domain_tag(
+            ctx.program_id,
+            &ctx.accounts.state.key(),
+            &cluster_genesis_hash,
+        );
+        {
+            let mut state = ctx.accounts.state.load_init()?;
+            state.init(
+                ctx.bumps.state,
+                ctx.accounts.admin.key(),
+                deposit_amount,
+                NATIVE_MINT,
+                domain,
+            );
+            state.tree_depth = tree_depth;
+            state.root_history_size = root_history_size;
         }
+        init_tree(
+            &ctx.accounts.tree,
+            ctx.accounts.state.key(),
+            0,
+            tree_depth,
+            root_history_size,
+        )
+    }
+
+    /// Same as `initialize`, but notes are backed by `deposit_amount` base units of
+    /// `mint`, held in a vault token account owned by the pool
+    pub fn initialize_token_pool(
+        ctx: Context<InitializeTokenPool>,
+        deposit_amount: u64,
+        cluster_genesis_hash: [u8; 32],
+        tree_depth: u8,
+        root_history_size: u16,
+    ) -> Result<()> {
+        check_tree_config(tree_depth, root_history_size)?;
+        check_mint_supported(&ctx.accounts.mint.to_account_info())?;
+        let domain = domain_tag(
+            ctx.program_id,
+            &ctx.accounts.state.key(),
+            &cluster_genesis_hash,
+        );
+        {
+            let mut state = ctx.accounts.state.load_init()?;
+            state.init(
+                ctx.bumps.state,
+                ctx.accounts.admin.key(),
+                deposit_amount,
+                ctx.accounts.mint.key(),
+                domain,
+            );
+            state.tree_depth = tree_depth;
+            state.root_history_size = root_history_size;
+        }
+        init_tree(
+            &ctx.accounts.tree,
+            ctx.accounts.state.key(),
+            0,
+            tree_depth,
+            root_history_size,
+        )
+    }
 
+    /// Admin only: halt deposits, and withdrawals as well when `halt_withdrawals` is set
+    pub fn pause(ctx: Context<AdminOnly>, halt_withdrawals: bool) -> Result<()> {
+        let mut state = ctx.accounts.state.load_mut()?;
+        state.paused = 1;
+        state.withdrawals_paused = halt_withdrawals as u8;
+
+        emit!(PausedEvent {
+            pool: ctx.accounts.state.key(),
+            withdrawals_paused: halt_withdrawals,
+        });
         Ok(())
     }
 
-    /// Deposit: takes a 32‐byte `commitment`, collects lamports, updates Merkle tree
-    pub fn deposit(ctx: Context<Deposit>, commitment: [u8; 32]) -> Result<()> {
+    /// Admin only: resume deposits and withdrawals
+    pub fn unpause(ctx: Context<AdminOnly>) -> Result<()> {
+        let mut state = ctx.accounts.state.load_mut()?;
+        state.paused = 0;
+        state.withdrawals_paused = 0;
+
+        emit!(UnpausedEvent {
+            pool: ctx.accounts.state.key()
+        });
+        Ok(())
+    }
+
+    /// Admin only: when `enabled`, deposits must pass the
+    /// `[COMMITMENT_SEED, pool, commitment]` account of each commitment as
+    /// remaining accounts, with its bump in the instruction data, and fail
+    /// with `DuplicateCommitment` on a repeat.
+    /// Only commitments deposited while enabled are recorded.
+    pub fn set_unique_commitments(ctx: Context<AdminOnly>, enabled: bool) -> Result<()> {
+        let mut state = ctx.accounts.state.load_mut()?;
+        state.unique_commitments = enabled as u8;
+
+        emit!(UniqueCommitmentsEvent {
+            pool: ctx.accounts.state.key(),
+            enabled,
+        });
+        Ok(())
+    }
+
+    /// Admin only: charge `fee_fixed` plus `fee_bps` of the amount withdrawn on
+    /// every withdrawal from now on, paid to the pool's treasury. The total for
+    /// a full withdrawal may not exceed `MAX_PROTOCOL_FEE_BPS` of the deposit amount.
+    /// Shielded pools take it from what `transact` withdraws; having no deposit
+    /// amount, they can only charge `fee_bps`.
+    pub fn set_protocol_fee(ctx: Context<AdminOnly>, fee_bps: u16, fee_fixed: u64) -> Result<()> {
+        let mut state = ctx.accounts.state.load_mut()?;
+        state.protocol_fee_bps = fee_bps;
+        state.protocol_fee_fixed = fee_fixed;
+        let cap = state.deposit_amount as u128 * MAX_PROTOCOL_FEE_BPS as u128 / 10_000;
         require!(
-            ctx.accounts.state.deposit_amount > 0,
-            ErrorCode::DepositAmountZero
+            fee_bps <= MAX_PROTOCOL_FEE_BPS
+                && state.protocol_fee(state.deposit_amount)? as u128 <= cap,
+            ErrorCode::ProtocolFeeTooHigh
         );
 
-        let state_info = ctx.accounts.state.to_account_info();
+        emit!(ProtocolFeeSetEvent {
+            pool: ctx.accounts.state.key(),
+            fee_bps,
+            fee_fixed,
+        });
+        Ok(())
+    }
+
+    /// Admin only: move `amount` of collected protocol fees to `destination`
+    /// (`destination_token_account` for token pools). A SOL treasury keeps its
+    /// rent-exempt minimum.
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+        let state = *ctx.accounts.state.load()?;
+        let accounts = &ctx.accounts;
+
+        let destination = if state.mint == NATIVE_MINT {
+            let (Some(treasury), Some(destination)) =
+                (accounts.treasury.as_ref(), accounts.destination.as_ref())
+            else {
+                return err!(ErrorCode::MissingTreasury);
+            };
+            let treasury_info = treasury.to_account_info();
+            let available = treasury_info
+                .lamports()
+                .saturating_sub(Rent::get()?.minimum_balance(Treasury::SPACE));
+            require!(amount <= available, ErrorCode::InsufficientTreasury);
+
+            **treasury_info.try_borrow_mut_lamports()? -= amount;
+            **destination.try_borrow_mut_lamports()? += amount;
+            destination.key()
+        } else {
+            let (Some(mint), Some(treasury), Some(destination), Some(token_program)) = (
+                accounts.mint.as_ref(),
+                accounts.treasury_token_account.as_ref(),
+                accounts.destination_token_account.as_ref(),
+                accounts.token_program.as_ref(),
+            ) else {
+                return err!(ErrorCode::MissingTreasury);
+            };
+            require!(amount <= treasury.amount, ErrorCode::InsufficientTreasury);
+
+            let amount_seed = state.deposit_amount.to_le_bytes();
+            pay_tokens(
+                token_program,
+                mint,
+                treasury,
+                destination.to_account_info(),
+                accounts.state.to_account_info(),
+                &[&[STATE_SEED, state.mint.as_ref(), &amount_seed, &[state.bump]]],
+                amount,
+            )?;
+            destination.key()
+        };
+
+        emit!(TreasuryWithdrawnEvent {
+            pool: ctx.accounts.state.key(),
+            destination,
+            amount,
+        });
+        Ok(())
+    }
+
+    /// Admin only: cap the relayer fee of every withdrawal at `max_fee`, set the
+    /// stake that registers a relayer without approval, and with
+    /// `registered_only` let only active registered relayers take a fee
+    pub fn set_relayer_policy(
+        ctx: Context<AdminOnly>,
+        max_fee: u64,
+        min_stake: u64,
+        registered_only: bool,
+    ) -> Result<()> {
+        let mut state = ctx.accounts.state.load_mut()?;
+        require!(
+            state.is_shielded() || max_fee <= state.deposit_amount,
+            ErrorCode::FeeTooHigh
+        );
+        state.max_relayer_fee = max_fee;
+        state.min_relayer_stake = min_stake;
+        state.registered_relayers_only = registered_only as u8;
+
+        emit!(RelayerPolicyEvent {
+            pool: ctx.accounts.state.key(),
+            max_fee,
+            min_stake,
+            registered_only,
+        });
+        Ok(())
+    }
+
+    /// Register the signer as a relayer of this pool, staking the pool's
+    /// current `min_relayer_stake`. With no stake required the entry stays
+    /// inactive until the administrator approves it.
+    pub fn register_relayer(
+        ctx: Context<RegisterRelayer>,
+        url: String,
+        max_fee: u64,
+    ) -> Result<()> {
+        require!(
+            url.len() <= MAX_RELAYER_URL_LEN,
+            ErrorCode::RelayerUrlTooLong
+        );
+        let stake = ctx.accounts.state.load()?.min_relayer_stake;
+        if stake > 0 {
+            invoke(
+                &system_instruction::transfer(
+                    &ctx.accounts.relayer.key(),
+                    &ctx.accounts.relayer_entry.key(),
+                    stake,
+                ),
+                &[
+                    ctx.accounts.relayer.to_account_info(),
+                    ctx.accounts.relayer_entry.to_account_info(),
+                ],
+            )?;
+        }
+
+        let entry = &mut ctx.accounts.relayer_entry;
+        entry.pool = ctx.accounts.state.key();
+        entry.relayer = ctx.accounts.relayer.key();
+        entry.max_fee = max_fee;
+        entry.stake = stake;
+        entry.approved = false;
+        entry.deregistered_at = None;
+        entry.url = url.clone();
+
+        emit!(RelayerRegisteredEvent {
+            pool: entry.pool,
+            relayer: entry.relayer,
+            url,
+            max_fee,
+            stake,
+        });
+        Ok(())
+    }
+
+    /// Stop relaying for this pool. The entry is inactive from now on; its stake
+    /// stays slashable for `RELAYER_UNBONDING_PERIOD`, then `withdraw_stake`
+    /// pays it back with the rent.
+    pub fn deregister_relayer(ctx: Context<DeregisterRelayer>) -> Result<()> {
+        let entry = &mut ctx.accounts.relayer_entry;
+        require!(
+            entry.deregistered_at.is_none(),
+            ErrorCode::RelayerNotRegistered
+        );
+        entry.deregistered_at = Some(Clock::get()?.unix_timestamp);
+
+        emit!(RelayerDeregisteredEvent {
+            pool: entry.pool,
+            relayer: entry.relayer,
+            unbonded_at: entry.unbonded_at().ok_or(error!(ErrorCode::MathError))?,
+        });
+        Ok(())
+    }
+
+    /// Add `amount` lamports to the signer's stake, e.g. to stay active after the
+    /// administrator raised `min_relayer_stake`
+    pub fn top_up_stake(ctx: Context<TopUpStake>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidInput);
+        require!(
+            ctx.accounts.relayer_entry.deregistered_at.is_none(),
+            ErrorCode::RelayerNotRegistered
+        );
         invoke(
             &system_instruction::transfer(
-                &ctx.accounts.depositor.key(),
-                &state_info.key(),
-                ctx.accounts.state.deposit_amount,
+                &ctx.accounts.relayer.key(),
+                &ctx.accounts.relayer_entry.key(),
+                amount,
             ),
-            &[ctx.accounts.depositor.to_account_info(), state_info.clone()],
+            &[
+                ctx.accounts.relayer.to_account_info(),
+                ctx.accounts.relayer_entry.to_account_info(),
+            ],
         )?;
 
-        let state = &mut ctx.accounts.state;
-        let leaf_index = state.next_index as usize;
-        require!(leaf_index < (1 << TREE_DEPTH), ErrorCode::TreeFull);
+        let entry = &mut ctx.accounts.relayer_entry;
+        entry.stake = entry
+            .stake
+            .checked_add(amount)
+            .ok_or(error!(ErrorCode::MathError))?;
+
+        emit!(RelayerStakeToppedUpEvent {
+            pool: entry.pool,
+            relayer: entry.relayer,
+            amount,
+            stake: entry.stake,
+        });
+        Ok(())
+    }
 
-        let mut node = commitment;
-        let mut idx = leaf_index;
+    /// Close a deregistered entry once its unbonding period is over, returning
+    /// what is left of the stake and the rent to the relayer
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>) -> Result<()> {
+        let entry = &ctx.accounts.relayer_entry;
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            entry.unbonded_at().is_some_and(|at| now >= at),
+            ErrorCode::StakeLocked
+        );
 
-        for level in 0..TREE_DEPTH {
-            if idx & 1 == 0 {
-                state.filled_subtrees[level] = node;
-                node = PoseidonHash::hash_pair(&node, &ZERO_HASHES[level]).0;
-            } else {
-                let left = state.filled_subtrees[level];
-                node = PoseidonHash::hash_pair(&left, &node).0;
-            }
-            idx >>= 1;
+        emit!(RelayerStakeWithdrawnEvent {
+            pool: entry.pool,
+            relayer: entry.relayer,
+            stake: entry.stake,
+        });
+        Ok(())
+    }
+
+    /// Admin only: take `amount` of a relayer's stake, e.g. after it front-ran
+    /// users, and send it to `destination`. Works until the stake is withdrawn;
+    /// a relayer left below `min_relayer_stake` is no longer active.
+    pub fn slash_relayer(ctx: Context<SlashRelayer>, amount: u64) -> Result<()> {
+        let entry = &mut ctx.accounts.relayer_entry;
+        require!(amount <= entry.stake, ErrorCode::SlashTooLarge);
+        entry.stake -= amount;
+        **entry.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.destination.try_borrow_mut_lamports()? += amount;
+
+        emit!(RelayerSlashedEvent {
+            pool: entry.pool,
+            relayer: entry.relayer,
+            destination: ctx.accounts.destination.key(),
+            amount,
+        });
+        Ok(())
+    }
+
+    /// Admin only: approve a registered relayer, or revoke an approval
+    pub fn approve_relayer(ctx: Context<ApproveRelayer>, approved: bool) -> Result<()> {
+        let entry = &mut ctx.accounts.relayer_entry;
+        entry.approved = approved;
+
+        emit!(RelayerApprovalEvent {
+            pool: entry.pool,
+            relayer: entry.relayer,
+            approved,
+        });
+        Ok(())
+    }
+
+    /// Admin only, step one of an ownership transfer: nominate `new_admin`.
+    /// A later proposal replaces a pending one.
+    pub fn propose_admin(ctx: Context<AdminOnly>, new_admin: Pubkey) -> Result<()> {
+        let mut state = ctx.accounts.state.load_mut()?;
+        state.pending_administrator = new_admin;
+
+        emit!(AdminProposedEvent {
+            pool: ctx.accounts.state.key(),
+            administrator: state.administrator,
+            pending_administrator: new_admin,
+        });
+        Ok(())
+    }
+
+    /// Step two: the nominee signs to take over. The nominee may be a PDA
+    /// (e.g. a multisig vault) signing through CPI.
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let mut state = ctx.accounts.state.load_mut()?;
+        let previous_administrator = state.administrator;
+        state.administrator = ctx.accounts.pending_administrator.key();
+        state.pending_administrator = Pubkey::default();
+
+        emit!(AdminAcceptedEvent {
+            pool: ctx.accounts.state.key(),
+            previous_administrator,
+            administrator: state.administrator,
+        });
+        Ok(())
+    }
+
+    /// Admin only: switch withdrawals to proofs of a new circuit, possibly on
+    /// another proving system. Proofs for the previous vkey stay valid for
+    /// `grace_period` seconds so notes proven against the old circuit are not
+    /// stranded mid-upgrade.
+    pub fn rotate_vkey(
+        ctx: Context<AdminOnly>,
+        new_verifier: VerifierKind,
+        new_vkey_hash: [u8; 32],
+        grace_period: i64,
+    ) -> Result<()> {
+        require!(
+            (0..=MAX_VKEY_GRACE_PERIOD).contains(&grace_period),
+            ErrorCode::InvalidGracePeriod
+        );
+        // a production build cannot be pointed at the test backend
+        #[cfg(not(feature = "mock-verifier"))]
+        require!(
+            new_verifier != VerifierKind::Mock,
+            ErrorCode::VerifierUnavailable
+        );
+        let now = Clock::get()?.unix_timestamp;
+        let mut state = ctx.accounts.state.load_mut()?;
+        let previous_verifier = VerifierKind::try_from(state.verifier)?;
+        let previous_vkey_hash = state.vkey_hash;
+
+        let mut previous_valid_until = 0;
+        if grace_period > 0 {
+            previous_valid_until = now
+                .checked_add(grace_period)
+                .ok_or(error!(ErrorCode::MathError))?;
+            // reuse an expired slot, or evict the one closest to expiring
+            let slot = state
+                .retired_vkeys
+                .iter_mut()
+                .min_by_key(|r| r.valid_until)
+                .unwrap();
+            *slot = RetiredVkey {
+                valid_until: previous_valid_until,
+                hash: previous_vkey_hash,
+                verifier: previous_verifier as u8,
+                _padding: [0; 7],
+            };
         }
+        state.verifier = new_verifier as u8;
+        state.vkey_hash = new_vkey_hash;
 
-        let next = ((state.current_root_index + 1) % ROOT_HISTORY_SIZE as u32) as usize;
-        state.root_history[next] = node;
-        state.current_root_index = next as u32;
-        state.current_root = node;
+        emit!(VkeyRotatedEvent {
+            pool: ctx.accounts.state.key(),
+            previous_verifier,
+            previous_vkey_hash,
+            previous_valid_until,
+            verifier: new_verifier,
+            vkey_hash: new_vkey_hash,
+        });
+        Ok(())
+    }
 
-        state.next_index += 1;
+    /// Admin only: publish a circom Groth16 verifying key so `rotate_vkey` can
+    /// point the pool at `vk.hash()` with the `CircomGroth16` backend
+    pub fn create_verifying_key(
+        ctx: Context<CreateVerifyingKey>,
+        vk: CircomVerifyingKey,
+    ) -> Result<()> {
+        ctx.accounts.verifying_key.set_inner(vk);
+        Ok(())
+    }
 
-        emit!(DepositEvent {
-            commitment,
-            leaf_index: leaf_index as u32,
-            depositor: *ctx.accounts.depositor.key,
+    /// Permissionless: once the current tree is full, open the next one and send
+    /// deposits there. The caller pays for the new tree account.
+    pub fn rollover_tree(ctx: Context<RolloverTree>) -> Result<()> {
+        let current_tree = ctx.accounts.current_tree.load()?;
+        require!(current_tree.is_full(), ErrorCode::TreeNotFull);
+
+        let mut state = ctx.accounts.state.load_mut()?;
+        let tree_index = state
+            .current_tree
+            .checked_add(1)
+            .ok_or(error!(ErrorCode::MathError))?;
+        init_tree(
+            &ctx.accounts.next_tree,
+            ctx.accounts.state.key(),
+            tree_index,
+            state.tree_depth,
+            state.root_history_size,
+        )?;
+        state.current_tree = tree_index;
+
+        emit!(TreeRolledOverEvent {
+            pool: ctx.accounts.state.key(),
+            tree_index,
+            previous_root: current_tree.current_root,
         });
+        Ok(())
+    }
+
+    /// Deposit: takes a 32‐byte `commitment`, collects lamports (or tokens), updates Merkle tree.
+    /// `encrypted_note` is emitted untouched in the `DepositEvent`, so a wallet can
+    /// recover the note from chain history with its viewing key.
+    /// `record_bump` is the bump of the commitment record, required while the
+    /// pool has `unique_commitments` on.
+    pub fn deposit<'info>(
+        ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+        commitment: [u8; 32],
+        encrypted_note: Option<Vec<u8>>,
+        record_bump: Option<u8>,
+    ) -> Result<()> {
+        ctx.accounts.deposit_notes(
+            &[commitment],
+            encrypted_note.map(|note| vec![note]).as_deref(),
+            record_bump.map(|bump| vec![bump]).as_deref(),
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Deposit several notes at once, paying `deposit_amount` for each. The
+    /// leaves are inserted together, so the tree is hashed once per level
+    /// rather than once per note and a single root enters the history.
+    /// `encrypted_notes`, if given, holds one note per commitment, and so does
+    /// `record_bumps` with the bumps of the commitment records.
+    pub fn deposit_batch<'info>(
+        ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+        commitments: Vec<[u8; 32]>,
+        encrypted_notes: Option<Vec<Vec<u8>>>,
+        record_bumps: Option<Vec<u8>>,
+    ) -> Result<()> {
+        require!(
+            (1..=MAX_DEPOSIT_BATCH).contains(&commitments.len()),
+            ErrorCode::InvalidBatchSize
+        );
+        if let Some(notes) = &encrypted_notes {
+            require!(
+                notes.len() == commitments.len(),
+                ErrorCode::InvalidBatchSize
+            );
+        }
+        ctx.accounts.deposit_notes(
+            &commitments,
+            encrypted_notes.as_deref(),
+            record_bumps.as_deref(),
+            ctx.remaining_accounts,
+        )
+    }
+
+    /// Withdraw: verify SNARK proof, check Merkle root & nullifier, pay out.
+    /// A partial withdrawal pays out `amount` and inserts the proof's change
+    /// commitment into `current_tree`, announced with `change_note` like a deposit.
+    /// `splits` pays part of the recipient's share to the payees committed to by
+    /// `splits_hash`, through one remaining account each (their token accounts
+    /// in token pools), in order.
+    pub fn withdraw<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Withdraw<'info>>,
+        nullifier_bytes: [u8; 32],
+        proof: Vec<u8>,
+        public_inputs: Vec<u8>,
+        change_note: Option<Vec<u8>>,
+        splits: Vec<WithdrawSplit>,
+    ) -> Result<()> {
+        // copied out so no borrow of the state data is held across the CPIs below
+        let state = *ctx.accounts.state.load()?;
+        require!(!state.is_shielded(), ErrorCode::WrongPoolKind);
+        require!(state.withdrawals_paused == 0, ErrorCode::WithdrawalsPaused);
+
+        // parse public inputs, exact length and known version only
+        let PublicInputs {
+            domain,
+            tree_index,
+            root,
+            nullifier_hash,
+            change_commitment,
+            recipient,
+            relayer,
+            splits_hash,
+            denomination,
+            amount,
+            fee,
+            refund,
+            expires_at,
+        } = PublicInputs::decode(&public_inputs)?;
+        // check that the users pass in correct nullifier
+        require!(nullifier_hash.eq(&nullifier_bytes), ErrorCode::InvalidInput);
+        // proof must be for this program, pool and cluster
+        require!(domain == state.domain, ErrorCode::DomainMismatch);
+        // no note is worth more than a deposit
+        require!(
+            denomination == state.deposit_amount,
+            ErrorCode::InvalidInput
+        );
+        require!(amount <= denomination, ErrorCode::AmountTooLarge);
+        let has_change = change_commitment != [0u8; 32];
+        if has_change {
+            check_commitment(&change_commitment)?;
+            require!(
+                change_note.as_deref().unwrap_or_default().len() <= MAX_ENCRYPTED_NOTE_LEN,
+                ErrorCode::EncryptedNoteTooLarge
+            );
+        } else {
+            // nothing would be inserted to announce it with
+            require!(change_note.is_none(), ErrorCode::InvalidInput);
+        }
+        if splits_hash == [0u8; 32] {
+            require!(splits.is_empty(), ErrorCode::InvalidSplits);
+        } else {
+            require!(
+                (1..=MAX_WITHDRAW_SPLITS).contains(&splits.len())
+                    && ctx.remaining_accounts.len() == splits.len()
+                    && hash_splits(&splits) == splits_hash,
+                ErrorCode::InvalidSplits
+            );
+        }
+        // bounds how long a relayer can sit on the proof
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            expires_at == 0 || now <= expires_at,
+            ErrorCode::ProofExpired
+        );
+        check_relayer_fee(&state, fee, ctx.accounts.relayer_entry.as_deref())?;
+
+        let circom_vk = ctx.accounts.verifying_key.as_deref().map(|vk| &**vk);
+        state.check_proof(now, circom_vk, &proof, &public_inputs)?;
+
+        // the root may come from an archived tree, which keeps its history
+        {
+            let tree_info = ctx.accounts.tree.to_account_info();
+            let data = tree_info.try_borrow_data()?;
+            let tree = TreeRef::new(&data)?;
+            require!(tree.header.index == tree_index, ErrorCode::InvalidInput);
+            require!(tree.is_known_root(&root), ErrorCode::InvalidRoot);
+        }
+
+        // mark the note spent; SOL pools pay the nullifier rent out of the note,
+        // token pools have the caller pay it
+        let is_native = state.mint == NATIVE_MINT;
+        let state_info = ctx.accounts.state.to_account_info();
+        let caller_info = ctx.accounts.caller.to_account_info();
+        let pool_key = state_info.key();
+        let nullifier_seeds: &[&[u8]] = &[
+            NULLIFIER_SEED,
+            pool_key.as_ref(),
+            &nullifier_hash,
+            &[ctx.bumps.nullifier],
+        ];
+        let rent_payer = if is_native {
+            RentPayer::Pool(&state_info)
+        } else {
+            RentPayer::Caller(&caller_info)
+        };
+        let rent_paid = create_nullifier(
+            &ctx.accounts.nullifier,
+            rent_payer,
+            &ctx.accounts.system_program,
+            nullifier_seeds,
+        )?;
+
+        // SOL pools: rent ⇒ nullifier, refund ⇒ caller, fee ⇒ relayer, rest ⇒ recipient.
+        // The refund reimburses costs the caller fronted, e.g. transaction fees, and
+        // is capped at the nullifier rent.
+        // Token pools: fee ⇒ relayer, rest ⇒ recipient, and the caller sends the
+        // recipient `refund` lamports of its own so a fresh wallet can pay fees.
+        // All of it comes out of `amount`, the change note keeps the rest, and
+        // split payees are paid out of the recipient's share.
+        let total = amount;
+        let protocol_fee = state.protocol_fee(amount)?;
+        let note_costs = if is_native {
+            let max_refund = Rent::get()?.minimum_balance(MAX_REFUND_ACCOUNT_SPACE);
+            require!(refund <= max_refund, ErrorCode::RefundTooLarge);
+            refund
+                .checked_add(rent_paid)
+                .ok_or(error!(ErrorCode::MathError))?
+        } else {
+            0
+        };
+        let to_recipient = total
+            .checked_sub(fee)
+            .and_then(|v| v.checked_sub(protocol_fee))
+            .and_then(|v| v.checked_sub(note_costs))
+            .ok_or(error!(ErrorCode::AmountTooSmall))?;
+        let split_total = splits
+            .iter()
+            .try_fold(0u64, |sum, split| sum.checked_add(split.amount))
+            .ok_or(error!(ErrorCode::MathError))?;
+        let to_recipient = to_recipient
+            .checked_sub(split_total)
+            .ok_or(error!(ErrorCode::InvalidSplits))?;
+
+        require!(
+            relayer.eq(&ctx.accounts.relayer.key()),
+            ErrorCode::InvalidInput
+        );
+        require!(
+            recipient.eq(&ctx.accounts.recipient.key()),
+            ErrorCode::InvalidInput
+        );
+
+        if is_native {
+            **state_info.try_borrow_mut_lamports()? -= refund;
+            **ctx.accounts.caller.try_borrow_mut_lamports()? += refund;
+
+            //fee -> relayer This uses SP1 network, so the fee will be taken for that
+            **state_info.try_borrow_mut_lamports()? -= fee;
+            **ctx.accounts.relayer.try_borrow_mut_lamports()? += fee;
+
+            // a fresh recipient has to end up rent exempt, out of its share of the note
+            check_rent_exempt(&ctx.accounts.recipient, to_recipient)?;
+            **state_info.try_borrow_mut_lamports()? -= to_recipient;
+            **ctx.accounts.recipient.try_borrow_mut_lamports()? += to_recipient;
+
+            for (split, payee) in splits.iter().zip(ctx.remaining_accounts) {
+                require_keys_eq!(*payee.key, split.recipient, ErrorCode::InvalidSplits);
+                check_rent_exempt(payee, split.amount)?;
+                **state_info.try_borrow_mut_lamports()? -= split.amount;
+                **payee.try_borrow_mut_lamports()? += split.amount;
+            }
+
+            if protocol_fee > 0 {
+                let treasury = ctx
+                    .accounts
+                    .treasury
+                    .as_ref()
+                    .ok_or(error!(ErrorCode::MissingTreasury))?;
+                **state_info.try_borrow_mut_lamports()? -= protocol_fee;
+                **treasury.to_account_info().try_borrow_mut_lamports()? += protocol_fee;
+            }
+        } else {
+            let accounts = &ctx.accounts;
+            let (Some(mint), Some(vault), Some(token_program)) = (
+                accounts.mint.as_ref(),
+                accounts.vault.as_ref(),
+                accounts.token_program.as_ref(),
+            ) else {
+                return err!(ErrorCode::MissingTokenAccounts);
+            };
+            // a recipient without a token account gets its associated one,
+            // the caller paying the rent and recouping it through `fee`
+            let recipient_token = match accounts.recipient_token_account.as_ref() {
+                Some(recipient_token) => {
+                    require!(recipient_token.owner == recipient, ErrorCode::InvalidInput);
+                    recipient_token.to_account_info()
+                }
+                None => {
+                    let (Some(ata), Some(ata_program)) = (
+                        accounts.recipient_ata.as_ref(),
+                        accounts.associated_token_program.as_ref(),
+                    ) else {
+                        return err!(ErrorCode::MissingTokenAccounts);
+                    };
+                    create_recipient_ata(
+                        ata,
+                        &accounts.recipient,
+                        mint,
+                        &caller_info,
+                        token_program,
+                        ata_program,
+                        &accounts.system_program,
+                    )?;
+                    ata.to_account_info()
+                }
+            };
+
+            let amount_seed = state.deposit_amount.to_le_bytes();
+            let signer_seeds: &[&[&[u8]]] =
+                &[&[STATE_SEED, state.mint.as_ref(), &amount_seed, &[state.bump]]];
 
+            if fee > 0 {
+                let relayer_token = accounts
+                    .relayer_token_account
+                    .as_ref()
+                    .ok_or(error!(ErrorCode::MissingTokenAccounts))?;
+                require!(relayer_token.owner == relayer, ErrorCode::InvalidInput);
+                pay_tokens(
+                    token_program,
+                    mint,
+                    vault,
+                    relayer_token.to_account_info(),
+                    state_info.clone(),
+                    signer_seeds,
+                    fee,
+                )?;
+            }
+            pay_tokens(
+                token_program,
+                mint,
+                vault,
+                recipient_token,
+                state_info.clone(),
+                signer_seeds,
+                to_recipient,
+            )?;
+            for (split, payee) in splits.iter().zip(ctx.remaining_accounts) {
+                let payee = InterfaceAccount::<TokenAccount>::try_from(payee)?;
+                require!(
+                    payee.mint == state.mint && payee.owner == split.recipient,
+                    ErrorCode::InvalidSplits
+                );
+                pay_tokens(
+                    token_program,
+                    mint,
+                    vault,
+                    payee.to_account_info(),
+                    state_info.clone(),
+                    signer_seeds,
+                    split.amount,
+                )?;
+            }
+            if protocol_fee > 0 {
+                let treasury = accounts
+                    .treasury_token_account
+                    .as_ref()
+                    .ok_or(error!(ErrorCode::MissingTreasury))?;
+                pay_tokens(
+                    token_program,
+                    mint,
+                    vault,
+                    treasury.to_account_info(),
+                    state_info.clone(),
+                    signer_seeds,
+                    protocol_fee,
+                )?;
+            }
+
+            if refund > 0 {
+                check_rent_exempt(&ctx.accounts.recipient, refund)?;
+                invoke(
+                    &system_instruction::transfer(
+                        &ctx.accounts.caller.key(),
+                        &ctx.accounts.recipient.key(),
+                        refund,
+                    ),
+                    &[
+                        ctx.accounts.caller.to_account_info(),
+                        ctx.accounts.recipient.to_account_info(),
+                    ],
+                )?;
+            }
+        }
+
+        if has_change {
+            let current_tree = ctx
+                .accounts
+                .current_tree
+                .as_ref()
+                .ok_or(error!(ErrorCode::InvalidInput))?;
+            let tree_info = current_tree.to_account_info();
+            let (tree_index, leaf_index) = {
+                let mut data = tree_info.try_borrow_mut_data()?;
+                let mut tree = TreeMut::new(&mut data);
+                (tree.header.index, tree.insert(change_commitment)?)
+            };
+            emit!(DepositEvent {
+                pool: pool_key,
+                commitment: change_commitment,
+                tree_index,
+                leaf_index,
+                depositor: ctx.accounts.caller.key(),
+                encrypted_note: change_note,
+            });
+        }
+
+        emit!(WithdrawEvent {
+            pool: pool_key,
+            nullifier_hash,
+            recipient,
+            relayer,
+            fee,
+            refund,
+            amount,
+            protocol_fee,
+            change_commitment,
+            splits,
+        });
         Ok(())
     }
 
-    /// Withdraw: verify SNARK proof, check Merkle root & nullifier, pay out
-    pub fn withdraw(
-        ctx: Context<Withdraw>,
+    /// Fixed-amount pools: hand a note to someone else without it leaving the
+    /// pool. The note behind `nullifier_bytes` is spent and `new_commitment`,
+    /// of the same value, takes its place; `encrypted_note` lets the recipient
+    /// find it. No lamports leave the pool, the caller pays the nullifier rent.
+    pub fn private_transfer(
+        ctx: Context<PrivateTransfer>,
         nullifier_bytes: [u8; 32],
         proof: Vec<u8>,
         public_inputs: Vec<u8>,
+        encrypted_note: Option<Vec<u8>>,
     ) -> Result<()> {
-        let state = &mut ctx.accounts.state;
-
-        // verify SP1 proof
-        verify_proof(
-            &proof,
-            &public_inputs,
-            MIXER_VKEY_HASH,
-            GROTH16_VK_4_0_0_RC3_BYTES,
-        )
-        .map_err(|_| error!(ErrorCode::InvalidProof))?;
+        let state = *ctx.accounts.state.load()?;
+        require!(!state.is_shielded(), ErrorCode::WrongPoolKind);
+        require!(state.withdrawals_paused == 0, ErrorCode::WithdrawalsPaused);
 
-        // parse public inputs slice
-        // Require >= 144, cause 144 len is compulsory
-        require!(public_inputs.len() >= 144, ErrorCode::InvalidInput);
+        let TransferInputs {
+            domain,
+            tree_index,
+            root,
+            nullifier_hash,
+            new_commitment,
+            expires_at,
+        } = TransferInputs::decode(&public_inputs)?;
+        require!(nullifier_hash == nullifier_bytes, ErrorCode::InvalidInput);
+        require!(domain == state.domain, ErrorCode::DomainMismatch);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            expires_at == 0 || now <= expires_at,
+            ErrorCode::ProofExpired
+        );
+        check_commitment(&new_commitment)?;
+        require!(
+            encrypted_note.as_deref().unwrap_or_default().len() <= MAX_ENCRYPTED_NOTE_LEN,
+            ErrorCode::EncryptedNoteTooLarge
+        );
 
-        msg!("public_inputs: {:?}", public_inputs);
+        let circom_vk = ctx.accounts.verifying_key.as_deref().map(|vk| &**vk);
+        state.check_proof(now, circom_vk, &proof, &public_inputs)?;
 
-        let root: [u8; 32] = public_inputs[0..32].try_into().unwrap();
-        let nullifier_hash: [u8; 32] = public_inputs[32..64].try_into().unwrap();
-        // check that the users pass in correct nullifier
-        require!(nullifier_hash.eq(&nullifier_bytes), ErrorCode::InvalidInput);
+        {
+            let tree_info = ctx.accounts.tree.to_account_info();
+            let data = tree_info.try_borrow_data()?;
+            let tree = TreeRef::new(&data)?;
+            require!(tree.header.index == tree_index, ErrorCode::InvalidInput);
+            require!(tree.is_known_root(&root), ErrorCode::InvalidRoot);
+        }
+
+        let pool_key = ctx.accounts.state.key();
+        create_nullifier(
+            &ctx.accounts.nullifier,
+            RentPayer::Caller(&ctx.accounts.caller.to_account_info()),
+            &ctx.accounts.system_program,
+            &[
+                NULLIFIER_SEED,
+                pool_key.as_ref(),
+                &nullifier_hash,
+                &[ctx.bumps.nullifier],
+            ],
+        )?;
 
-        let recipient_bytes: [u8; 32] = public_inputs[64..96].try_into().unwrap();
-        let relayer_bytes: [u8; 32] = public_inputs[96..128].try_into().unwrap();
-        let fee = u64::from_le_bytes(public_inputs[128..136].try_into().unwrap());
-        let refund = u64::from_le_bytes(public_inputs[136..144].try_into().unwrap());
+        let tree_info = ctx.accounts.current_tree.to_account_info();
+        let (tree_index, leaf_index) = {
+            let mut data = tree_info.try_borrow_mut_data()?;
+            let mut tree = TreeMut::new(&mut data);
+            (tree.header.index, tree.insert(new_commitment)?)
+        };
 
-        let mut found = false;
-        for &r in state.root_history.iter() {
-            if r == root {
-                found = true;
-                break;
+        // the new leaf is announced like a deposit, so scanners and indexers pick it up
+        emit!(DepositEvent {
+            pool: pool_key,
+            commitment: new_commitment,
+            tree_index,
+            leaf_index,
+            depositor: ctx.accounts.caller.key(),
+            encrypted_note,
+        });
+        emit!(PrivateTransferEvent {
+            pool: pool_key,
+            nullifier_hash,
+            commitment: new_commitment,
+        });
+        Ok(())
+    }
+
+    /// Shielded pools only: spend up to two notes and create up to two, moving
+    /// `ext_amount` in from the caller or out to `recipient`. Unused input and
+    /// output slots are all zero in the public inputs. Outputs are announced
+    /// with a `DepositEvent` each, `encrypted_outputs` holding one note per
+    /// output if given. A withdrawal pays the protocol fee out of `ext_amount`
+    /// and, like `withdraw`, must leave a fresh SOL recipient rent exempt.
+    pub fn transact(
+        ctx: Context<Transact>,
+        input_nullifiers: [[u8; 32]; 2],
+        proof: Vec<u8>,
+        public_inputs: Vec<u8>,
+        encrypted_outputs: Vec<Vec<u8>>,
+    ) -> Result<()> {
+        // copied out so no borrow of the state data is held across the CPIs below
+        let state = *ctx.accounts.state.load()?;
+        require!(state.is_shielded(), ErrorCode::WrongPoolKind);
+        require!(state.withdrawals_paused == 0, ErrorCode::WithdrawalsPaused);
+
+        let inputs = TransactInputs::decode(&public_inputs)?;
+        require!(
+            inputs.input_nullifiers == input_nullifiers,
+            ErrorCode::InvalidInput
+        );
+        require!(inputs.domain == state.domain, ErrorCode::DomainMismatch);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            inputs.expires_at == 0 || now <= inputs.expires_at,
+            ErrorCode::ProofExpired
+        );
+        if inputs.ext_amount > 0 {
+            require!(state.paused == 0, ErrorCode::DepositsPaused);
+        }
+        require!(
+            inputs.relayer == ctx.accounts.relayer.key()
+                && inputs.recipient == ctx.accounts.recipient.key(),
+            ErrorCode::InvalidInput
+        );
+        check_relayer_fee(&state, inputs.fee, ctx.accounts.relayer_entry.as_deref())?;
+
+        let outputs: Vec<[u8; 32]> = inputs
+            .output_commitments
+            .into_iter()
+            .filter(|c| *c != [0u8; 32])
+            .collect();
+        for commitment in &outputs {
+            check_commitment(commitment)?;
+        }
+        require!(
+            encrypted_outputs.is_empty() || encrypted_outputs.len() == outputs.len(),
+            ErrorCode::InvalidInput
+        );
+        require!(
+            encrypted_outputs
+                .iter()
+                .all(|note| note.len() <= MAX_ENCRYPTED_NOTE_LEN),
+            ErrorCode::EncryptedNoteTooLarge
+        );
+
+        let circom_vk = ctx.accounts.verifying_key.as_deref().map(|vk| &**vk);
+        state.check_proof(now, circom_vk, &proof, &public_inputs)?;
+
+        {
+            let tree_info = ctx.accounts.tree.to_account_info();
+            let data = tree_info.try_borrow_data()?;
+            let tree = TreeRef::new(&data)?;
+            require!(
+                tree.header.index == inputs.tree_index,
+                ErrorCode::InvalidInput
+            );
+            require!(tree.is_known_root(&inputs.root), ErrorCode::InvalidRoot);
+        }
+
+        // spend the inputs; the caller pays the nullifier rent and is expected
+        // to recover it through `fee`
+        let pool_key = ctx.accounts.state.key();
+        let caller_info = ctx.accounts.caller.to_account_info();
+        let nullifier_accounts = [&ctx.accounts.nullifier_0, &ctx.accounts.nullifier_1];
+        let nullifier_bumps = [ctx.bumps.nullifier_0, ctx.bumps.nullifier_1];
+        for i in 0..2 {
+            if input_nullifiers[i] == [0u8; 32] {
+                continue;
+            }
+            create_nullifier(
+                nullifier_accounts[i],
+                RentPayer::Caller(&caller_info),
+                &ctx.accounts.system_program,
+                &[
+                    NULLIFIER_SEED,
+                    pool_key.as_ref(),
+                    &input_nullifiers[i],
+                    &[nullifier_bumps[i]],
+                ],
+            )?;
+        }
+
+        // value in from the caller, out to recipient, relayer and treasury; the
+        // protocol fee comes out of what is withdrawn
+        let withdrawn = if inputs.ext_amount < 0 {
+            inputs.ext_amount.unsigned_abs()
+        } else {
+            0
+        };
+        let protocol_fee = if withdrawn > 0 {
+            state.protocol_fee(withdrawn)?
+        } else {
+            0
+        };
+        let to_recipient = withdrawn
+            .checked_sub(protocol_fee)
+            .ok_or(error!(ErrorCode::AmountTooSmall))?;
+        let accounts = &mut *ctx.accounts;
+        let state_info = accounts.state.to_account_info();
+        if state.mint == NATIVE_MINT {
+            if inputs.ext_amount > 0 {
+                invoke(
+                    &system_instruction::transfer(
+                        &accounts.caller.key(),
+                        &state_info.key(),
+                        inputs.ext_amount as u64,
+                    ),
+                    &[accounts.caller.to_account_info(), state_info.clone()],
+                )?;
+            }
+            let paid_out = withdrawn
+                .checked_add(inputs.fee)
+                .ok_or(error!(ErrorCode::MathError))?;
+            **state_info.try_borrow_mut_lamports()? = state_info
+                .lamports()
+                .checked_sub(paid_out)
+                .ok_or(error!(ErrorCode::MathError))?;
+            // a fresh recipient has to end up rent exempt, like in `withdraw`
+            check_rent_exempt(&accounts.recipient, to_recipient)?;
+            **accounts.recipient.try_borrow_mut_lamports()? += to_recipient;
+            **accounts.relayer.try_borrow_mut_lamports()? += inputs.fee;
+            if protocol_fee > 0 {
+                let treasury = accounts
+                    .treasury
+                    .as_ref()
+                    .ok_or(error!(ErrorCode::MissingTreasury))?;
+                **treasury.to_account_info().try_borrow_mut_lamports()? += protocol_fee;
+            }
+        } else {
+            let (Some(mint), Some(vault), Some(token_program)) = (
+                accounts.mint.as_ref(),
+                accounts.vault.as_mut(),
+                accounts.token_program.as_ref(),
+            ) else {
+                return err!(ErrorCode::MissingTokenAccounts);
+            };
+            if inputs.ext_amount > 0 {
+                let from = accounts
+                    .caller_token_account
+                    .as_ref()
+                    .ok_or(error!(ErrorCode::MissingTokenAccounts))?;
+                deposit_tokens(
+                    token_program,
+                    mint,
+                    from,
+                    vault,
+                    accounts.caller.to_account_info(),
+                    inputs.ext_amount as u64,
+                )?;
+            }
+
+            let amount_seed = state.deposit_amount.to_le_bytes();
+            let signer_seeds: &[&[&[u8]]] =
+                &[&[STATE_SEED, state.mint.as_ref(), &amount_seed, &[state.bump]]];
+            for (amount, owner, token_account) in [
+                (
+                    to_recipient,
+                    inputs.recipient,
+                    &accounts.recipient_token_account,
+                ),
+                (inputs.fee, inputs.relayer, &accounts.relayer_token_account),
+            ] {
+                if amount == 0 {
+                    continue;
+                }
+                let to = token_account
+                    .as_ref()
+                    .ok_or(error!(ErrorCode::MissingTokenAccounts))?;
+                require!(to.owner == owner, ErrorCode::InvalidInput);
+                pay_tokens(
+                    token_program,
+                    mint,
+                    vault,
+                    to.to_account_info(),
+                    state_info.clone(),
+                    signer_seeds,
+                    amount,
+                )?;
+            }
+            if protocol_fee > 0 {
+                let treasury = accounts
+                    .treasury_token_account
+                    .as_ref()
+                    .ok_or(error!(ErrorCode::MissingTreasury))?;
+                pay_tokens(
+                    token_program,
+                    mint,
+                    vault,
+                    treasury.to_account_info(),
+                    state_info.clone(),
+                    signer_seeds,
+                    protocol_fee,
+                )?;
             }
         }
-        require!(found, ErrorCode::InvalidRoot);
 
-        // transfers: refund ⇒ caller, fee ⇒ relayer, rest ⇒ recipient
-        let total = state.deposit_amount;
-        let to_recipient = total
-            .checked_sub(fee)
-            .and_then(|v| v.checked_sub(refund))
-            .ok_or(error!(ErrorCode::MathError))?;
-        require!(refund == 0, ErrorCode::MathError);
+        if !outputs.is_empty() {
+            let tree_info = accounts.current_tree.to_account_info();
+            let (tree_index, first_leaf) = {
+                let mut data = tree_info.try_borrow_mut_data()?;
+                let mut tree = TreeMut::new(&mut data);
+                (tree.header.index, tree.insert_batch(&outputs)?)
+            };
+            for (i, commitment) in outputs.iter().enumerate() {
+                emit!(DepositEvent {
+                    pool: pool_key,
+                    commitment: *commitment,
+                    tree_index,
+                    leaf_index: first_leaf + i as u32,
+                    depositor: accounts.caller.key(),
+                    encrypted_note: encrypted_outputs.get(i).cloned(),
+                });
+            }
+        }
+
+        emit!(TransactEvent {
+            pool: pool_key,
+            input_nullifiers,
+            ext_amount: inputs.ext_amount,
+            fee: inputs.fee,
+            protocol_fee,
+            recipient: inputs.recipient,
+            relayer: inputs.relayer,
+        });
+        Ok(())
+    }
+
+    /// View: whether `nullifier_hash` has been spent in this pool. The answer is
+    /// returned as `bool` return data, meant to be read through `simulateTransaction`.
+    pub fn is_spent(ctx: Context<IsSpent>, _nullifier_hash: [u8; 32]) -> Result<bool> {
+        Ok(nullifier::is_spent(&ctx.accounts.nullifier))
+    }
+}
+
+// One pool per (mint, deposit_amount) pair
+#[derive(Accounts)]
+#[instruction(
+    deposit_amount: u64,
+    cluster_genesis_hash: [u8; 32],
+    tree_depth: u8,
+    root_history_size: u16,
+)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        seeds = [STATE_SEED, NATIVE_MINT.as_ref(), &deposit_amount.to_le_bytes()],
+        bump,
+        payer = admin,
+        space = State::SPACE,
+    )]
+    pub state: AccountLoader<'info, State>,
+    #[account(
+        init,
+        seeds = [TREE_SEED, state.key().as_ref(), &0u32.to_le_bytes()],
+        bump,
+        payer = admin,
+        space = MerkleTree::space(tree_depth, root_history_size),
+    )]
+    pub tree: AccountLoader<'info, MerkleTree>,
+    #[account(
+        init,
+        seeds = [TREASURY_SEED, state.key().as_ref()],
+        bump,
+        payer = admin,
+        space = Treasury::SPACE,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    deposit_amount: u64,
+    cluster_genesis_hash: [u8; 32],
+    tree_depth: u8,
+    root_history_size: u16,
+)]
+pub struct InitializeTokenPool<'info> {
+    #[account(
+        init,
+        seeds = [STATE_SEED, mint.key().as_ref(), &deposit_amount.to_le_bytes()],
+        bump,
+        payer = admin,
+        space = State::SPACE,
+    )]
+    pub state: AccountLoader<'info, State>,
+    #[account(
+        init,
+        seeds = [TREE_SEED, state.key().as_ref(), &0u32.to_le_bytes()],
+        bump,
+        payer = admin,
+        space = MerkleTree::space(tree_depth, root_history_size),
+    )]
+    pub tree: AccountLoader<'info, MerkleTree>,
+    #[account(mint::token_program = token_program)]
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        init,
+        seeds = [VAULT_SEED, state.key().as_ref()],
+        bump,
+        payer = admin,
+        token::mint = mint,
+        token::authority = state,
+        token::token_program = token_program,
+    )]
+    pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+    /// Collects the protocol fee, like the vault owned by the pool
+    #[account(
+        init,
+        seeds = [TREASURY_SEED, state.key().as_ref()],
+        bump,
+        payer = admin,
+        token::mint = mint,
+        token::authority = state,
+        token::token_program = token_program,
+    )]
+    pub treasury: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AdminOnly<'info> {
+    #[account(
+        mut,
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+        has_one = administrator @ ErrorCode::Unauthorized,
+    )]
+    pub state: AccountLoader<'info, State>,
+    pub administrator: Signer<'info>,
+}
+
+// SOL pools pass `treasury`, token pools the token accounts
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+        has_one = administrator @ ErrorCode::Unauthorized,
+    )]
+    pub state: AccountLoader<'info, State>,
+    pub administrator: Signer<'info>,
+    #[account(mut, seeds = [TREASURY_SEED, state.key().as_ref()], bump)]
+    pub treasury: Option<Account<'info, Treasury>>,
+    /// CHECK: receives the lamports of a SOL pool's treasury, chosen by the administrator
+    #[account(mut)]
+    pub destination: Option<UncheckedAccount<'info>>,
+    #[account(address = state.load()?.mint)]
+    pub mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    #[account(mut, seeds = [TREASURY_SEED, state.key().as_ref()], bump)]
+    pub treasury_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(mut, token::mint = state.load()?.mint)]
+    pub destination_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+#[instruction(vk: CircomVerifyingKey)]
+pub struct CreateVerifyingKey<'info> {
+    #[account(
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+        has_one = administrator @ ErrorCode::Unauthorized,
+    )]
+    pub state: AccountLoader<'info, State>,
+    #[account(
+        init,
+        seeds = [VERIFYING_KEY_SEED, state.key().as_ref(), &vk.hash()],
+        bump,
+        payer = administrator,
+        space = CircomVerifyingKey::SPACE,
+    )]
+    pub verifying_key: Box<Account<'info, CircomVerifyingKey>>,
+    #[account(mut)]
+    pub administrator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+        constraint = state.load()?.pending_administrator == pending_administrator.key()
+            @ ErrorCode::Unauthorized,
+    )]
+    pub state: AccountLoader<'info, State>,
+    pub pending_administrator: Signer<'info>,
+}
+
+// Anyone may submit a transfer proof
+#[derive(Accounts)]
+#[instruction(nullifier_bytes: [u8; 32])]
+pub struct PrivateTransfer<'info> {
+    #[account(
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+    )]
+    pub state: AccountLoader<'info, State>,
+    /// Tree the proof's root belongs to, `tree_index` of the public inputs
+    #[account(constraint = tree.load()?.pool == state.key() @ ErrorCode::InvalidInput)]
+    pub tree: AccountLoader<'info, MerkleTree>,
+    /// Receives the new commitment; may be the same account as `tree`
+    #[account(
+        mut,
+        seeds = [TREE_SEED, state.key().as_ref(), &state.load()?.current_tree.to_le_bytes()],
+        bump,
+    )]
+    pub current_tree: AccountLoader<'info, MerkleTree>,
+    /// CHECK: created in `private_transfer` once the proof checks out
+    #[account(
+        mut,
+        seeds = [NULLIFIER_SEED, state.key().as_ref(), nullifier_bytes.as_ref()],
+        bump
+    )]
+    pub nullifier: UncheckedAccount<'info>,
+    /// Pays the nullifier rent
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    /// Only read by pools on the `CircomGroth16` verifier
+    pub verifying_key: Option<Box<Account<'info, CircomVerifyingKey>>>,
+    pub system_program: Program<'info, System>,
+}
+
+// Shielded pools only. Token accounts are only required by token pools, and
+// only for the legs that move tokens.
+#[derive(Accounts)]
+#[instruction(input_nullifiers: [[u8; 32]; 2])]
+pub struct Transact<'info> {
+    #[account(
+        mut,
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+    )]
+    pub state: AccountLoader<'info, State>,
+    /// Tree the proof's root belongs to, `tree_index` of the public inputs
+    #[account(constraint = tree.load()?.pool == state.key() @ ErrorCode::InvalidInput)]
+    pub tree: AccountLoader<'info, MerkleTree>,
+    /// Receives the outputs; may be the same account as `tree`
+    #[account(
+        mut,
+        seeds = [TREE_SEED, state.key().as_ref(), &state.load()?.current_tree.to_le_bytes()],
+        bump,
+    )]
+    pub current_tree: AccountLoader<'info, MerkleTree>,
+    /// CHECK: created in `transact` unless the input slot is unused
+    #[account(
+        mut,
+        seeds = [NULLIFIER_SEED, state.key().as_ref(), input_nullifiers[0].as_ref()],
+        bump
+    )]
+    pub nullifier_0: UncheckedAccount<'info>,
+    /// CHECK: created in `transact` unless the input slot is unused
+    #[account(
+        mut,
+        seeds = [NULLIFIER_SEED, state.key().as_ref(), input_nullifiers[1].as_ref()],
+        bump
+    )]
+    pub nullifier_1: UncheckedAccount<'info>,
+    /// Pays nullifier rent and, for deposits, `ext_amount`
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    /// CHECK: validated by SNARK
+    #[account(mut)]
+    pub recipient: AccountInfo<'info>,
+    /// CHECK: validated by SNARK
+    #[account(mut)]
+    pub relayer: AccountInfo<'info>,
+    /// Only needed by pools with `registered_relayers_only` when fee > 0
+    #[account(seeds = [RELAYER_SEED, state.key().as_ref(), relayer.key().as_ref()], bump)]
+    pub relayer_entry: Option<Account<'info, RelayerEntry>>,
+    /// Only read by pools on the `CircomGroth16` verifier
+    pub verifying_key: Option<Box<Account<'info, CircomVerifyingKey>>>,
+    #[account(address = state.load()?.mint)]
+    pub mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    #[account(mut, seeds = [VAULT_SEED, state.key().as_ref()], bump)]
+    pub vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// Source of `ext_amount` when depositing
+    #[account(mut, token::mint = state.load()?.mint)]
+    pub caller_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// Owner validated by SNARK, only needed when withdrawing
+    #[account(mut, token::mint = state.load()?.mint)]
+    pub recipient_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// Owner validated by SNARK, only needed when fee > 0
+    #[account(mut, token::mint = state.load()?.mint)]
+    pub relayer_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// SOL pools, only needed to withdraw while the pool charges a protocol fee
+    #[account(mut, seeds = [TREASURY_SEED, state.key().as_ref()], bump)]
+    pub treasury: Option<Account<'info, Treasury>>,
+    /// Token pools, only needed to withdraw while the pool charges a protocol fee
+    #[account(mut, seeds = [TREASURY_SEED, state.key().as_ref()], bump)]
+    pub treasury_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+// Anyone may offer to relay
+#[derive(Accounts)]
+pub struct RegisterRelayer<'info> {
+    #[account(
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+    )]
+    pub state: AccountLoader<'info, State>,
+    #[account(
+        init,
+        seeds = [RELAYER_SEED, state.key().as_ref(), relayer.key().as_ref()],
+        bump,
+        payer = relayer,
+        space = RelayerEntry::SPACE,
+    )]
+    pub relayer_entry: Account<'info, RelayerEntry>,
+    #[account(mut)]
+    pub relayer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
 
-        let state_info = ctx.accounts.state.to_account_info();
+#[derive(Accounts)]
+pub struct DeregisterRelayer<'info> {
+    #[account(
+        mut,
+        seeds = [RELAYER_SEED, relayer_entry.pool.as_ref(), relayer.key().as_ref()],
+        bump,
+        has_one = relayer,
+    )]
+    pub relayer_entry: Account<'info, RelayerEntry>,
+    pub relayer: Signer<'info>,
+}
 
-        **state_info.try_borrow_mut_lamports()? -= refund;
-        **ctx.accounts.caller.try_borrow_mut_lamports()? += refund;
+#[derive(Accounts)]
+pub struct TopUpStake<'info> {
+    #[account(
+        mut,
+        seeds = [RELAYER_SEED, relayer_entry.pool.as_ref(), relayer.key().as_ref()],
+        bump,
+        has_one = relayer,
+    )]
+    pub relayer_entry: Account<'info, RelayerEntry>,
+    #[account(mut)]
+    pub relayer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
 
-        //fee -> relayer This uses SP1 network, so the fee will be taken for that
-        let relayer = Pubkey::new_from_array(relayer_bytes);
-        require!(
-            relayer.eq(&ctx.accounts.relayer.key()),
-            ErrorCode::InvalidInput
-        );
-        **state_info.try_borrow_mut_lamports()? -= fee;
-        **ctx.accounts.relayer.try_borrow_mut_lamports()? += fee;
+#[derive(Accounts)]
+pub struct WithdrawStake<'info> {
+    #[account(
+        mut,
+        seeds = [RELAYER_SEED, relayer_entry.pool.as_ref(), relayer.key().as_ref()],
+        bump,
+        has_one = relayer,
+        close = relayer,
+    )]
+    pub relayer_entry: Account<'info, RelayerEntry>,
+    #[account(mut)]
+    pub relayer: Signer<'info>,
+}
 
-        let recipient = Pubkey::new_from_array(recipient_bytes);
-        require!(
-            recipient.eq(&ctx.accounts.recipient.key()),
-            ErrorCode::InvalidInput
-        );
-        **state_info.try_borrow_mut_lamports()? -= to_recipient;
-        **ctx.accounts.recipient.try_borrow_mut_lamports()? += to_recipient;
+#[derive(Accounts)]
+pub struct ApproveRelayer<'info> {
+    #[account(
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+        has_one = administrator @ ErrorCode::Unauthorized,
+    )]
+    pub state: AccountLoader<'info, State>,
+    pub administrator: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [RELAYER_SEED, state.key().as_ref(), relayer_entry.relayer.as_ref()],
+        bump,
+    )]
+    pub relayer_entry: Account<'info, RelayerEntry>,
+}
 
-        emit!(WithdrawEvent {
-            nullifier_hash,
-            recipient: Pubkey::new_from_array(recipient_bytes),
-            relayer: Pubkey::new_from_array(relayer_bytes),
-            fee,
-            refund,
-        });
-        Ok(())
-    }
+#[derive(Accounts)]
+pub struct SlashRelayer<'info> {
+    #[account(
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+        has_one = administrator @ ErrorCode::Unauthorized,
+    )]
+    pub state: AccountLoader<'info, State>,
+    pub administrator: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [RELAYER_SEED, state.key().as_ref(), relayer_entry.relayer.as_ref()],
+        bump,
+    )]
+    pub relayer_entry: Account<'info, RelayerEntry>,
+    /// CHECK: receives the slashed lamports, chosen by the administrator
+    #[account(mut)]
+    pub destination: UncheckedAccount<'info>,
 }
 
+// Anyone may open the next tree once the current one is full
 #[derive(Accounts)]
-pub struct Initialize<'info> {
+pub struct RolloverTree<'info> {
+    #[account(
+        mut,
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+    )]
+    pub state: AccountLoader<'info, State>,
+    #[account(
+        seeds = [TREE_SEED, state.key().as_ref(), &state.load()?.current_tree.to_le_bytes()],
+        bump,
+    )]
+    pub current_tree: AccountLoader<'info, MerkleTree>,
     #[account(
         init,
-        seeds = [STATE_SEED],
+        seeds = [TREE_SEED, state.key().as_ref(), &(state.load()?.current_tree + 1).to_le_bytes()],
         bump,
-        payer = admin,
-        space = State::SPACE,
+        payer = payer,
+        space = MerkleTree::space(state.load()?.tree_depth, state.load()?.root_history_size),
     )]
-    pub state: Box<Account<'info, State>>,
+    pub next_tree: AccountLoader<'info, MerkleTree>,
     #[account(mut)]
-    pub admin: Signer<'info>,
+    pub payer: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
+// Token accounts are only required by token pools
 #[derive(Accounts)]
 pub struct Deposit<'info> {
     #[account(
         mut,
-        seeds = [STATE_SEED],
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+    )]
+    pub state: AccountLoader<'info, State>,
+    #[account(
+        mut,
+        seeds = [TREE_SEED, state.key().as_ref(), &state.load()?.current_tree.to_le_bytes()],
         bump,
     )]
-    pub state: Box<Account<'info, State>>,
+    pub tree: AccountLoader<'info, MerkleTree>,
     pub depositor: Signer<'info>,
+    #[account(address = state.load()?.mint)]
+    pub mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    #[account(mut, seeds = [VAULT_SEED, state.key().as_ref()], bump)]
+    pub vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(mut, token::mint = state.load()?.mint)]
+    pub depositor_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
     pub system_program: Program<'info, System>,
 }
 
+impl<'info> Deposit<'info> {
+    /// Take `deposit_amount` per commitment from the depositor and append the
+    /// commitments to the pool's current tree. `encrypted_notes` is parallel to
+    /// `commitments`, and so are the commitment records in `remaining_accounts`
+    /// and their `record_bumps` when the pool has `unique_commitments` on.
+    fn deposit_notes(
+        &mut self,
+        commitments: &[[u8; 32]],
+        encrypted_notes: Option<&[Vec<u8>]>,
+        record_bumps: Option<&[u8]>,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        for commitment in commitments {
+            check_commitment(commitment)?;
+        }
+        require!(
+            encrypted_notes
+                .unwrap_or_default()
+                .iter()
+                .all(|note| note.len() <= MAX_ENCRYPTED_NOTE_LEN),
+            ErrorCode::EncryptedNoteTooLarge
+        );
+        // copied out so no borrow of the state data is held across the CPIs below
+        let state = *self.state.load()?;
+        require!(state.deposit_amount > 0, ErrorCode::DepositAmountZero);
+        require!(state.paused == 0, ErrorCode::DepositsPaused);
+        let deposit_amount = state
+            .deposit_amount
+            .checked_mul(commitments.len() as u64)
+            .ok_or(error!(ErrorCode::MathError))?;
+
+        if state.mint == NATIVE_MINT {
+            let state_info = self.state.to_account_info();
+            invoke(
+                &system_instruction::transfer(
+                    &self.depositor.key(),
+                    &state_info.key(),
+                    deposit_amount,
+                ),
+                &[self.depositor.to_account_info(), state_info.clone()],
+            )?;
+        } else {
+            let (Some(mint), Some(vault), Some(from), Some(token_program)) = (
+                self.mint.as_ref(),
+                self.vault.as_mut(),
+                self.depositor_token_account.as_ref(),
+                self.token_program.as_ref(),
+            ) else {
+                return err!(ErrorCode::MissingTokenAccounts);
+            };
+            deposit_tokens(
+                token_program,
+                mint,
+                from,
+                vault,
+                self.depositor.to_account_info(),
+                deposit_amount,
+            )?;
+        }
+
+        if state.unique_commitments != 0 {
+            record_commitments(
+                &self.state.key(),
+                commitments,
+                remaining_accounts,
+                record_bumps.unwrap_or_default(),
+                &self.depositor.to_account_info(),
+                &self.system_program,
+            )?;
+        }
+
+        // a full tree fails with TreeFull until someone calls `rollover_tree`
+        let tree_info = self.tree.to_account_info();
+        let (tree_index, first_leaf) = {
+            let mut data = tree_info.try_borrow_mut_data()?;
+            let mut tree = TreeMut::new(&mut data);
+            (tree.header.index, tree.insert_batch(commitments)?)
+        };
+
+        for (i, commitment) in commitments.iter().enumerate() {
+            emit!(DepositEvent {
+                pool: self.state.key(),
+                commitment: *commitment,
+                tree_index,
+                leaf_index: first_leaf + i as u32,
+                depositor: *self.depositor.key,
+                encrypted_note: encrypted_notes.map(|notes| notes[i].clone()),
+            });
+        }
+
+        Ok(())
+    }
+}
+
 // Anyone may withdraw
 #[derive(Accounts)]
 #[instruction(nullifier_bytes: [u8; 32])]
 pub struct Withdraw<'info> {
     #[account(
         mut,
-        seeds = [STATE_SEED],
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+    )]
+    pub state: AccountLoader<'info, State>,
+    /// Tree the proof's root belongs to, `tree_index` of the public inputs
+    #[account(constraint = tree.load()?.pool == state.key() @ ErrorCode::InvalidInput)]
+    pub tree: AccountLoader<'info, MerkleTree>,
+    /// Receives the change commitment of a partial withdrawal; may be the same
+    /// account as `tree`
+    #[account(
+        mut,
+        seeds = [TREE_SEED, state.key().as_ref(), &state.load()?.current_tree.to_le_bytes()],
         bump,
     )]
-    pub state: Box<Account<'info, State>>,
-    #[account(init, seeds = [nullifier_bytes.as_ref()], bump, payer = caller, space = 8)]
-    /// CHECK: validated by SNARK
-    pub nullifier: Box<Account<'info, Nullifier>>,
+    pub current_tree: Option<AccountLoader<'info, MerkleTree>>,
+    /// CHECK: created in `withdraw` once the proof checks out
+    #[account(
+        mut,
+        seeds = [NULLIFIER_SEED, state.key().as_ref(), nullifier_bytes.as_ref()],
+        bump
+    )]
+    pub nullifier: UncheckedAccount<'info>,
     #[account(mut)]
     pub caller: Signer<'info>,
     /// CHECK: validated by SNARK
@@ -304,34 +1911,210 @@ pub struct Withdraw<'info> {
     /// CHECK: validated by SNARK
     #[account(mut)]
     pub relayer: AccountInfo<'info>,
+    /// Only read by pools on the `CircomGroth16` verifier
+    pub verifying_key: Option<Box<Account<'info, CircomVerifyingKey>>>,
+    #[account(address = state.load()?.mint)]
+    pub mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    #[account(mut, seeds = [VAULT_SEED, state.key().as_ref()], bump)]
+    pub vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// Owner validated by SNARK; leave out to pay `recipient_ata` instead
+    #[account(mut, token::mint = state.load()?.mint)]
+    pub recipient_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// CHECK: token pools without `recipient_token_account`: the recipient's
+    /// associated token account, created by `withdraw` if it does not exist
+    #[account(mut)]
+    pub recipient_ata: Option<UncheckedAccount<'info>>,
+    /// Owner validated by SNARK, only needed when fee > 0
+    #[account(mut, token::mint = state.load()?.mint)]
+    pub relayer_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// Only needed by pools with `registered_relayers_only` when fee > 0
+    #[account(seeds = [RELAYER_SEED, state.key().as_ref(), relayer.key().as_ref()], bump)]
+    pub relayer_entry: Option<Account<'info, RelayerEntry>>,
+    /// SOL pools, only needed while the pool charges a protocol fee
+    #[account(mut, seeds = [TREASURY_SEED, state.key().as_ref()], bump)]
+    pub treasury: Option<Account<'info, Treasury>>,
+    /// Token pools, only needed while the pool charges a protocol fee
+    #[account(mut, seeds = [TREASURY_SEED, state.key().as_ref()], bump)]
+    pub treasury_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// Only needed to create `recipient_ata`
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
     pub system_program: Program<'info, System>,
 }
 
-#[account]
+#[derive(Accounts)]
+#[instruction(nullifier_hash: [u8; 32])]
+pub struct IsSpent<'info> {
+    #[account(
+        seeds = [STATE_SEED, state.load()?.mint.as_ref(), &state.load()?.deposit_amount.to_le_bytes()],
+        bump = state.load()?.bump,
+    )]
+    pub state: AccountLoader<'info, State>,
+    /// CHECK: only its owner is read
+    #[account(seeds = [NULLIFIER_SEED, state.key().as_ref(), nullifier_hash.as_ref()], bump)]
+    pub nullifier: UncheckedAccount<'info>,
+}
+
+/// Zero-copy, so `deposit` and `withdraw` read it in place instead of
+/// deserializing and re-serializing it. The layout is `repr(C)` and existing
+/// fields never move: a new field either takes bytes out of `_reserved`,
+/// keeping `State::SPACE`, or goes at the end, growing it.
+#[account(zero_copy)]
 #[derive(Debug)]
 pub struct State {
-    pub bump: u8,
     pub administrator: Pubkey,
-    pub next_index: u32,
-    pub current_root_index: u32,
-    pub current_root: [u8; 32],
-    pub filled_subtrees: [[u8; 32]; TREE_DEPTH],
-    pub root_history: [[u8; 32]; ROOT_HISTORY_SIZE],
+    /// Nominated by `propose_admin`, becomes administrator on `accept_admin`;
+    /// `Pubkey::default()` when there is no nominee
+    pub pending_administrator: Pubkey,
+    pub mint: Pubkey,
+    /// `public_inputs::domain_tag` of this pool, withdrawal proofs must commit to it
+    pub domain: [u8; 32],
+    /// Identifies the circuit for `verifier`, see `VerifierKind`
+    pub vkey_hash: [u8; 32],
+    /// Previous vkey hashes, accepted until their `valid_until`
+    pub retired_vkeys: [RetiredVkey; RETIRED_VKEY_SLOTS],
     pub deposit_amount: u64,
+    /// Index of the `MerkleTree` deposits go into
+    pub current_tree: u32,
+    /// Shape of every tree of the pool, fixed at `initialize`
+    pub root_history_size: u16,
+    pub tree_depth: u8,
+    pub bump: u8,
+    /// Deposits are rejected while non-zero
+    pub paused: u8,
+    /// Emergency stop: withdrawals are rejected while non-zero
+    pub withdrawals_paused: u8,
+    /// `VerifierKind` withdrawal proofs are checked with
+    pub verifier: u8,
+    /// While non-zero every deposit records its commitments, rejecting repeats
+    pub unique_commitments: u8,
+    /// Proportional part of the protocol fee, see `protocol_fee`
+    pub protocol_fee_bps: u16,
+    /// While non-zero a withdrawal paying a fee must name an active `RelayerEntry`
+    pub registered_relayers_only: u8,
+    pub _reserved: [u8; 1],
+    /// Flat part of the protocol fee, in lamports or base units of `mint`
+    pub protocol_fee_fixed: u64,
+    /// Highest relayer `fee` a withdrawal may carry
+    pub max_relayer_fee: u64,
+    /// Lamports a relayer stakes to register without administrator approval;
+    /// 0 means approval is the only way in
+    pub min_relayer_stake: u64,
+}
+
+#[zero_copy]
+#[derive(Debug, Default)]
+pub struct RetiredVkey {
+    /// unix timestamp, 0 for an empty slot
+    pub valid_until: i64,
+    pub hash: [u8; 32],
+    /// `VerifierKind`
+    pub verifier: u8,
+    pub _padding: [u8; 7],
 }
 
 #[account]
 #[derive(Debug)]
 pub struct Nullifier {}
 
+impl Nullifier {
+    pub const SPACE: usize = 8; // discriminator
+}
+
+/// Holds the protocol fees of a SOL pool, at `[TREASURY_SEED, pool]`. Token
+/// pools keep theirs in a token account at the same address.
+#[account]
+#[derive(Debug)]
+pub struct Treasury {}
+
+impl Treasury {
+    pub const SPACE: usize = 8; // discriminator
+}
+
+/// Exists at `[COMMITMENT_SEED, pool, commitment]` once `commitment` was
+/// deposited into a pool with `unique_commitments` on
+#[account]
+#[derive(Debug)]
+pub struct CommitmentRecord {}
+
 impl State {
-    const SPACE: usize = 8  // discriminator
-        + 1                // bump
-        + 32               // administrator
-        + 4 + 4 + 32       // next_index, current_root_index, current_root
-        + 32 * TREE_DEPTH  // filled_subtrees
-        + 32 * ROOT_HISTORY_SIZE // root_history
-        + 8; // deposit_amount
+    fn init(
+        &mut self,
+        bump: u8,
+        administrator: Pubkey,
+        deposit_amount: u64,
+        mint: Pubkey,
+        domain: [u8; 32],
+    ) {
+        self.bump = bump;
+        self.administrator = administrator;
+        self.pending_administrator = Pubkey::default();
+        self.current_tree = 0;
+        self.deposit_amount = deposit_amount;
+        self.mint = mint;
+        self.domain = domain;
+        self.paused = 0;
+        self.withdrawals_paused = 0;
+        self.verifier = VerifierKind::Sp1Groth16 as u8;
+        self.unique_commitments = 0;
+        self.protocol_fee_bps = 0;
+        self.protocol_fee_fixed = 0;
+        self.registered_relayers_only = 0;
+        // fees in shielded pools are bounded by the note values, proven by the circuit
+        self.max_relayer_fee = if deposit_amount == 0 {
+            u64::MAX
+        } else {
+            deposit_amount
+        };
+        self.min_relayer_stake = 0;
+        self.vkey_hash = DEFAULT_VKEY_HASH;
+        self.retired_vkeys = [RetiredVkey::default(); RETIRED_VKEY_SLOTS];
+    }
+
+    const SPACE: usize = 8 + std::mem::size_of::<State>();
+
+    /// Taken from a withdrawal of `amount` for the treasury: the flat part plus
+    /// `protocol_fee_bps` of `amount`
+    fn protocol_fee(&self, amount: u64) -> Result<u64> {
+        let share = amount as u128 * self.protocol_fee_bps as u128 / 10_000;
+        (share as u64)
+            .checked_add(self.protocol_fee_fixed)
+            .ok_or(error!(ErrorCode::MathError))
+    }
+
+    /// Shielded pools hold notes of any value, moved with `transact`; they are
+    /// the pools created with a `deposit_amount` of 0
+    fn is_shielded(&self) -> bool {
+        self.deposit_amount == 0
+    }
+
+    /// Accept `proof` if it verifies against the current vkey, or a retired one
+    /// still in its grace window
+    fn check_proof(
+        &self,
+        now: i64,
+        circom_vk: Option<&CircomVerifyingKey>,
+        proof: &[u8],
+        public_inputs: &[u8],
+    ) -> Result<()> {
+        let verified = self.accepted_vkeys(now).any(|(verifier, vkey_hash)| {
+            verifier::verify_proof(verifier, circom_vk, proof, public_inputs, &vkey_hash).is_ok()
+        });
+        require!(verified, ErrorCode::InvalidProof);
+        Ok(())
+    }
+
+    /// Unknown verifier bytes, which only a layout bug could produce, are skipped
+    fn accepted_vkeys(&self, now: i64) -> impl Iterator<Item = (VerifierKind, [u8; 32])> + '_ {
+        std::iter::once((self.verifier, self.vkey_hash))
+            .chain(
+                self.retired_vkeys
+                    .iter()
+                    .filter(move |r| r.valid_until >= now)
+                    .map(|r| (r.verifier, r.hash)),
+            )
+            .filter_map(|(verifier, hash)| Some((VerifierKind::try_from(verifier).ok()?, hash)))
+    }
 }
 
 /// Errors
@@ -353,20 +2136,97 @@ pub enum ErrorCode {
     HasherError,
     #[msg("Deposit amount is zero")]
     DepositAmountZero,
+    #[msg("Token pool accounts missing")]
+    MissingTokenAccounts,
+    #[msg("Mint uses an unsupported Token-2022 extension")]
+    UnsupportedMint,
+    #[msg("Vault received an unexpected amount")]
+    DepositAmountMismatch,
+    #[msg("Signer is not the pool administrator")]
+    Unauthorized,
+    #[msg("Deposits are paused")]
+    DepositsPaused,
+    #[msg("Withdrawals are paused")]
+    WithdrawalsPaused,
+    #[msg("Grace period out of range")]
+    InvalidGracePeriod,
+    #[msg("Circom verifying key account missing")]
+    MissingVerifyingKey,
+    #[msg("Verifier backend not built into this program")]
+    VerifierUnavailable,
+    #[msg("Unsupported public inputs version")]
+    UnsupportedPublicInputsVersion,
+    #[msg("Proof was generated for another program, pool or cluster")]
+    DomainMismatch,
+    #[msg("Proof has expired")]
+    ProofExpired,
+    #[msg("Refund exceeds the allowed amount")]
+    RefundTooLarge,
+    #[msg("Current Merkle tree still has free leaves")]
+    TreeNotFull,
+    #[msg("Tree depth or root history size out of range")]
+    InvalidTreeConfig,
+    #[msg("Batch must hold between 1 and MAX_DEPOSIT_BATCH commitments")]
+    InvalidBatchSize,
+    #[msg("Encrypted note exceeds MAX_ENCRYPTED_NOTE_LEN")]
+    EncryptedNoteTooLarge,
+    #[msg("Commitment is not a canonical BN254 scalar")]
+    CommitmentNotInField,
+    #[msg("Commitment is a reserved empty-leaf value")]
+    ReservedCommitment,
+    #[msg("Commitment was already deposited")]
+    DuplicateCommitment,
+    #[msg("Commitment record accounts missing or at the wrong address")]
+    InvalidCommitmentRecord,
+    #[msg("Protocol fee exceeds MAX_PROTOCOL_FEE_BPS of the deposit amount")]
+    ProtocolFeeTooHigh,
+    #[msg("Treasury account required")]
+    MissingTreasury,
+    #[msg("Treasury holds less than requested")]
+    InsufficientTreasury,
+    #[msg("Relayer fee exceeds the allowed maximum")]
+    FeeTooHigh,
+    #[msg("Relayer is not registered with this pool or not active")]
+    RelayerNotRegistered,
+    #[msg("Relayer URL exceeds MAX_RELAYER_URL_LEN")]
+    RelayerUrlTooLong,
+    #[msg("Stake is locked until the unbonding period after deregistration ends")]
+    StakeLocked,
+    #[msg("Slash exceeds the relayer's stake")]
+    SlashTooLarge,
+    #[msg("Instruction not available for this kind of pool")]
+    WrongPoolKind,
+    #[msg("Withdrawal amount exceeds the pool denomination")]
+    AmountTooLarge,
+    #[msg("Split payees do not match the proof or the accounts passed")]
+    InvalidSplits,
+    #[msg("Payout would leave the recipient below the rent-exempt minimum")]
+    RecipientNotRentExempt,
+    #[msg("Withdrawal amount does not cover the relayer fee, protocol fee and note costs")]
+    AmountTooSmall,
 }
 
 #[event]
 #[derive(Debug)]
 pub struct DepositEvent {
+    pub pool: Pubkey,
+
     pub commitment: [u8; 32],
 
+    pub tree_index: u32,
+
     pub leaf_index: u32,
 
     pub depositor: Pubkey,
+
+    /// Note ciphertext for the depositor's viewing key, if one was attached
+    pub encrypted_note: Option<Vec<u8>>,
 }
 
 #[event]
 pub struct WithdrawEvent {
+    pub pool: Pubkey,
+
     pub nullifier_hash: [u8; 32],
 
     pub recipient: Pubkey,
@@ -376,4 +2236,179 @@ pub struct WithdrawEvent {
     pub fee: u64,
 
     pub refund: u64,
+
+    /// Taken out of the pool, less than `deposit_amount` for a partial withdrawal
+    pub amount: u64,
+
+    /// Sent to the pool's treasury
+    pub protocol_fee: u64,
+
+    /// Note keeping the rest of a partial withdrawal, zero otherwise
+    pub change_commitment: [u8; 32],
+
+    /// Payees paid out of the recipient's share, besides `recipient`
+    pub splits: Vec<WithdrawSplit>,
+}
+
+#[event]
+pub struct PrivateTransferEvent {
+    pub pool: Pubkey,
+
+    pub nullifier_hash: [u8; 32],
+
+    /// The recipient's new note, see the `DepositEvent` for its position
+    pub commitment: [u8; 32],
+}
+
+#[event]
+pub struct TransactEvent {
+    pub pool: Pubkey,
+
+    /// All zero for an unused input
+    pub input_nullifiers: [[u8; 32]; 2],
+
+    /// > 0 deposited by the caller, < 0 paid to `recipient`
+    pub ext_amount: i64,
+
+    pub fee: u64,
+
+    /// Sent to the pool's treasury out of a withdrawal
+    pub protocol_fee: u64,
+
+    pub recipient: Pubkey,
+
+    pub relayer: Pubkey,
+}
+
+#[event]
+pub struct PausedEvent {
+    pub pool: Pubkey,
+
+    pub withdrawals_paused: bool,
+}
+
+#[event]
+pub struct UnpausedEvent {
+    pub pool: Pubkey,
+}
+
+#[event]
+pub struct ProtocolFeeSetEvent {
+    pub pool: Pubkey,
+    pub fee_bps: u16,
+    pub fee_fixed: u64,
+}
+
+#[event]
+pub struct TreasuryWithdrawnEvent {
+    pub pool: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct RelayerPolicyEvent {
+    pub pool: Pubkey,
+    pub max_fee: u64,
+    pub min_stake: u64,
+    pub registered_only: bool,
+}
+
+#[event]
+pub struct RelayerRegisteredEvent {
+    pub pool: Pubkey,
+    pub relayer: Pubkey,
+    pub url: String,
+    pub max_fee: u64,
+    pub stake: u64,
+}
+
+#[event]
+pub struct RelayerDeregisteredEvent {
+    pub pool: Pubkey,
+    pub relayer: Pubkey,
+    /// From this unix timestamp on the relayer can `withdraw_stake`
+    pub unbonded_at: i64,
+}
+
+#[event]
+pub struct RelayerStakeToppedUpEvent {
+    pub pool: Pubkey,
+    pub relayer: Pubkey,
+    pub amount: u64,
+    /// Stake after the top-up
+    pub stake: u64,
+}
+
+#[event]
+pub struct RelayerStakeWithdrawnEvent {
+    pub pool: Pubkey,
+    pub relayer: Pubkey,
+    pub stake: u64,
+}
+
+#[event]
+pub struct RelayerSlashedEvent {
+    pub pool: Pubkey,
+    pub relayer: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct RelayerApprovalEvent {
+    pub pool: Pubkey,
+    pub relayer: Pubkey,
+    pub approved: bool,
+}
+
+#[event]
+pub struct UniqueCommitmentsEvent {
+    pub pool: Pubkey,
+    pub enabled: bool,
+}
+
+#[event]
+pub struct AdminProposedEvent {
+    pub pool: Pubkey,
+
+    pub administrator: Pubkey,
+
+    pub pending_administrator: Pubkey,
+}
+
+#[event]
+pub struct AdminAcceptedEvent {
+    pub pool: Pubkey,
+
+    pub previous_administrator: Pubkey,
+
+    pub administrator: Pubkey,
+}
+
+#[event]
+pub struct VkeyRotatedEvent {
+    pub pool: Pubkey,
+
+    pub previous_verifier: VerifierKind,
+
+    pub previous_vkey_hash: [u8; 32],
+
+    /// 0 when the previous vkey was revoked immediately
+    pub previous_valid_until: i64,
+
+    pub verifier: VerifierKind,
+
+    pub vkey_hash: [u8; 32],
+}
+
+#[event]
+pub struct TreeRolledOverEvent {
+    pub pool: Pubkey,
+
+    /// Index of the tree new deposits go into
+    pub tree_index: u32,
+
+    /// Final root of the tree that filled up
+    pub previous_root: [u8; 32],
 }
@@ -0,0 +1,229 @@
+use std::mem::size_of;
+
+use anchor_lang::prelude::*;
+
+use crate::nozeromerkle::PoseidonHash;
+use crate::{ErrorCode, MAX_ROOT_HISTORY_SIZE, MAX_TREE_DEPTH, ZERO_HASHES};
+
+/// Header of one incremental Merkle tree of a pool, at `[TREE_SEED, pool, index]`.
+/// Deposits go into the pool's `current_tree`; once it is full `rollover_tree`
+/// opens the next one. Full trees are never written again, so their root
+/// history stays valid for withdrawals.
+///
+/// The header is followed by a tail sized by the pool's tree configuration:
+///
+/// ```text
+/// filled_subtrees  32 * depth
+/// zeros            32 * depth
+/// root_history     32 * root_history_size
+/// ```
+///
+/// Use `TreeRef` / `TreeMut` to get at the tail.
+#[account(zero_copy)]
+#[derive(Debug)]
+pub struct MerkleTree {
+    pub pool: Pubkey,
+    pub current_root: [u8; 32],
+    pub index: u32,
+    pub next_index: u32,
+    pub current_root_index: u32,
+    pub depth: u32,
+    pub root_history_size: u32,
+}
+
+impl MerkleTree {
+    pub const fn space(depth: u8, root_history_size: u16) -> usize {
+        8 + size_of::<MerkleTree>() + 32 * (2 * depth as usize + root_history_size as usize)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.next_index as u64 >= 1 << self.depth
+    }
+}
+
+// the largest tree must still be creatable through CPI
+const _: () = assert!(
+    MerkleTree::space(MAX_TREE_DEPTH, MAX_ROOT_HISTORY_SIZE)
+        <= anchor_lang::solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE
+);
+
+pub fn check_tree_config(depth: u8, root_history_size: u16) -> Result<()> {
+    require!(
+        (1..=MAX_TREE_DEPTH).contains(&depth)
+            && (1..=MAX_ROOT_HISTORY_SIZE).contains(&root_history_size),
+        ErrorCode::InvalidTreeConfig
+    );
+    Ok(())
+}
+
+/// Read-only view of a tree account, also usable by clients on fetched account data
+pub struct TreeRef<'a> {
+    pub header: &'a MerkleTree,
+    pub filled_subtrees: &'a [[u8; 32]],
+    pub zeros: &'a [[u8; 32]],
+    pub root_history: &'a [[u8; 32]],
+}
+
+impl<'a> TreeRef<'a> {
+    /// `data` is the whole account, discriminator included
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        require!(
+            data.len() >= 8 + size_of::<MerkleTree>(),
+            anchor_lang::error::ErrorCode::AccountDidNotDeserialize
+        );
+        let (head, tail) = data[8..].split_at(size_of::<MerkleTree>());
+        let header: &MerkleTree = bytemuck::from_bytes(head);
+        let (depth, history) = (header.depth as usize, header.root_history_size as usize);
+        require!(
+            tail.len() >= 32 * (2 * depth + history),
+            anchor_lang::error::ErrorCode::AccountDidNotDeserialize
+        );
+
+        let nodes: &[[u8; 32]] = bytemuck::cast_slice(&tail[..32 * (2 * depth + history)]);
+        let (filled_subtrees, rest) = nodes.split_at(depth);
+        let (zeros, root_history) = rest.split_at(depth);
+        Ok(TreeRef {
+            header,
+            filled_subtrees,
+            zeros,
+            root_history,
+        })
+    }
+
+    pub fn is_known_root(&self, root: &[u8; 32]) -> bool {
+        self.root_history.iter().any(|r| r == root)
+    }
+}
+
+/// Writable view of a tree account
+pub struct TreeMut<'a> {
+    pub header: &'a mut MerkleTree,
+    filled_subtrees: &'a mut [[u8; 32]],
+    zeros: &'a mut [[u8; 32]],
+    root_history: &'a mut [[u8; 32]],
+}
+
+impl<'a> TreeMut<'a> {
+    /// `data` is the whole account, sized with `MerkleTree::space`. The
+    /// discriminator is not checked, so this also works on a tree being created.
+    pub fn new(data: &'a mut [u8]) -> Self {
+        let (head, tail) = data[8..].split_at_mut(size_of::<MerkleTree>());
+        let header: &mut MerkleTree = bytemuck::from_bytes_mut(head);
+        let (depth, history) = (header.depth as usize, header.root_history_size as usize);
+
+        let nodes: &mut [[u8; 32]] =
+            bytemuck::cast_slice_mut(&mut tail[..32 * (2 * depth + history)]);
+        let (filled_subtrees, rest) = nodes.split_at_mut(depth);
+        let (zeros, root_history) = rest.split_at_mut(depth);
+        TreeMut {
+            header,
+            filled_subtrees,
+            zeros,
+            root_history,
+        }
+    }
+
+    /// Set up an empty tree. Zero hashes for the levels `ZERO_HASHES` covers are
+    /// copied, deeper ones are hashed here.
+    pub fn init(
+        data: &'a mut [u8],
+        pool: Pubkey,
+        index: u32,
+        depth: u8,
+        root_history_size: u16,
+    ) -> Self {
+        {
+            let header: &mut MerkleTree =
+                bytemuck::from_bytes_mut(&mut data[8..8 + size_of::<MerkleTree>()]);
+            header.pool = pool;
+            header.index = index;
+            header.next_index = 0;
+            header.current_root_index = 0;
+            header.depth = depth as u32;
+            header.root_history_size = root_history_size as u32;
+        }
+        let tree = TreeMut::new(data);
+
+        for level in 0..tree.zeros.len() {
+            tree.zeros[level] = match ZERO_HASHES.get(level) {
+                Some(zero) => *zero,
+                None => {
+                    let below = tree.zeros[level - 1];
+                    PoseidonHash::hash_pair(&below, &below).0
+                }
+            };
+        }
+        tree.filled_subtrees.copy_from_slice(&*tree.zeros);
+        let top = tree.zeros[tree.zeros.len() - 1];
+        tree.header.current_root = top;
+        for slot in tree.root_history.iter_mut() {
+            *slot = top;
+        }
+        tree
+    }
+
+    /// Append `commitment`, returning its leaf index
+    pub fn insert(&mut self, commitment: [u8; 32]) -> Result<u32> {
+        self.insert_batch(&[commitment])
+    }
+
+    /// Append `commitments` as consecutive leaves, returning the index of the
+    /// first. The tree is rebuilt a level at a time, so nodes shared by several
+    /// new leaves are hashed once, and only the final root enters the history.
+    pub fn insert_batch(&mut self, commitments: &[[u8; 32]]) -> Result<u32> {
+        require!(!commitments.is_empty(), ErrorCode::InvalidInput);
+        let first_index = self.header.next_index;
+        let end = (first_index as u64)
+            .checked_add(commitments.len() as u64)
+            .ok_or(error!(ErrorCode::MathError))?;
+        require!(end <= 1 << self.header.depth, ErrorCode::TreeFull);
+
+        // nodes of the current level that change, starting at index `start`
+        let mut nodes = commitments.to_vec();
+        let mut start = first_index as usize;
+
+        for level in 0..self.filled_subtrees.len() {
+            // a right-hand first node pairs with the stored left sibling
+            if start & 1 == 1 {
+                nodes.insert(0, self.filled_subtrees[level]);
+                start -= 1;
+            }
+            // the last left-hand node is what later insertions pair with
+            let last_left = (nodes.len() - 1) & !1;
+            self.filled_subtrees[level] = nodes[last_left];
+
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| {
+                    let right = pair.get(1).unwrap_or(&self.zeros[level]);
+                    PoseidonHash::hash_pair(&pair[0], right).0
+                })
+                .collect();
+            start >>= 1;
+        }
+        let root = nodes[0];
+
+        let header = &mut *self.header;
+        let next = ((header.current_root_index + 1) % header.root_history_size) as usize;
+        self.root_history[next] = root;
+        header.current_root_index = next as u32;
+        header.current_root = root;
+
+        header.next_index = end as u32;
+        Ok(first_index)
+    }
+}
+
+/// `TreeMut::init` on an account just created by an `init` constraint
+pub fn init_tree(
+    tree: &AccountLoader<MerkleTree>,
+    pool: Pubkey,
+    index: u32,
+    depth: u8,
+    root_history_size: u16,
+) -> Result<()> {
+    let info = tree.to_account_info();
+    let mut data = info.try_borrow_mut_data()?;
+    TreeMut::init(&mut data, pool, index, depth, root_history_size);
+    Ok(())
+}
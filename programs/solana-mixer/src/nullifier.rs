@@ -0,0 +1,95 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{program::invoke, system_instruction};
+use anchor_lang::system_program::{self, Allocate, Assign};
+
+use crate::{ErrorCode, Nullifier};
+
+/// Who puts up the rent for a new nullifier (or commitment record) account
+pub enum RentPayer<'a, 'info> {
+    /// SOL pools: the note itself, debited straight from the pool account
+    Pool(&'a AccountInfo<'info>),
+    /// Token pools, and deposits: the transaction signer
+    Caller(&'a AccountInfo<'info>),
+}
+
+/// A nullifier account only ever becomes program owned in `create_nullifier`
+pub fn is_spent(nullifier: &AccountInfo) -> bool {
+    *nullifier.owner == crate::ID
+}
+
+/// Create the `[NULLIFIER_SEED, pool, nullifier_hash]` account, marking the note
+/// spent. Returns the lamports taken from `payer`. Any lamports already sitting
+/// on the address count towards the rent, so pre-funding it cannot block a
+/// withdrawal.
+pub fn create_nullifier<'info>(
+    nullifier: &AccountInfo<'info>,
+    payer: RentPayer<'_, 'info>,
+    system_program: &Program<'info, System>,
+    signer_seeds: &[&[u8]],
+) -> Result<u64> {
+    require!(!is_spent(nullifier), ErrorCode::NullifierAlreadyUsed);
+    create_marker(
+        nullifier,
+        payer,
+        system_program,
+        signer_seeds,
+        Nullifier::DISCRIMINATOR,
+    )
+}
+
+/// Create a PDA holding nothing but `discriminator`, whose existence is the
+/// record. The caller checks the account is not already program owned.
+pub fn create_marker<'info>(
+    account: &AccountInfo<'info>,
+    payer: RentPayer<'_, 'info>,
+    system_program: &Program<'info, System>,
+    signer_seeds: &[&[u8]],
+    discriminator: &[u8],
+) -> Result<u64> {
+    let space = discriminator.len();
+    let rent = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(account.lamports());
+
+    if rent > 0 {
+        match payer {
+            RentPayer::Pool(pool) => {
+                **pool.try_borrow_mut_lamports()? = pool
+                    .lamports()
+                    .checked_sub(rent)
+                    .ok_or(error!(ErrorCode::MathError))?;
+                **account.try_borrow_mut_lamports()? += rent;
+            }
+            RentPayer::Caller(caller) => invoke(
+                &system_instruction::transfer(caller.key, account.key, rent),
+                &[caller.clone(), account.clone()],
+            )?,
+        }
+    }
+
+    let signer = &[signer_seeds];
+    system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            Allocate {
+                account_to_allocate: account.clone(),
+            },
+            signer,
+        ),
+        space as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            Assign {
+                account_to_assign: account.clone(),
+            },
+            signer,
+        ),
+        &crate::ID,
+    )?;
+    account
+        .try_borrow_mut_data()?
+        .copy_from_slice(discriminator);
+    Ok(rent)
+}
@@ -0,0 +1,307 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+
+use crate::ErrorCode;
+
+/// Layout version of every statement below, bumped whenever a field is added,
+/// removed or moved
+pub const PUBLIC_INPUTS_VERSION: u8 = 7;
+
+/// Second byte of the public inputs: which statement a proof makes. A pool
+/// has one circuit, so this keeps e.g. a transfer proof from passing as a
+/// withdrawal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ProofKind {
+    Withdraw = 0,
+    Transfer = 1,
+    Transact = 2,
+}
+
+const DOMAIN_SEPARATOR: &[u8] = b"solana-mixer/v1";
+
+/// Binds proofs to one deployment: sha256 of a fixed prefix, the program id, the
+/// pool address and the genesis hash of the cluster. The program cannot read the
+/// genesis hash itself, so it is supplied once at `initialize`.
+pub fn domain_tag(program_id: &Pubkey, pool: &Pubkey, cluster_genesis_hash: &[u8; 32]) -> [u8; 32] {
+    hashv(&[
+        DOMAIN_SEPARATOR,
+        program_id.as_ref(),
+        pool.as_ref(),
+        cluster_genesis_hash,
+    ])
+    .to_bytes()
+}
+
+/// Values committed by a withdrawal proof. The prover, clients and the program
+/// all go through `encode`/`decode`, so the byte layout lives only here:
+///
+/// ```text
+/// version             1
+/// kind                1  ProofKind::Withdraw
+/// domain             32
+/// tree_index          4  LE
+/// root               32
+/// nullifier_hash     32
+/// change_commitment  32  all zero for a full withdrawal
+/// recipient          32
+/// relayer            32
+/// splits_hash        32  hash_splits of the payees, all zero for none
+/// denomination        8  LE
+/// amount              8  LE
+/// fee                 8  LE
+/// refund              8  LE
+/// expires_at          8  LE
+/// ```
+///
+/// Notes commit to their value. The circuit proves the spent note is worth at
+/// most `denomination` and that `amount` plus the value of the change note
+/// equals it, so a deposit committing to more than it paid cannot be cashed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicInputs {
+    /// `domain_tag` of the pool the proof is for
+    pub domain: [u8; 32],
+    /// `MerkleTree` of the pool that `root` belongs to
+    pub tree_index: u32,
+    pub root: [u8; 32],
+    pub nullifier_hash: [u8; 32],
+    /// Note holding what is left of the spent one, inserted by `withdraw`
+    pub change_commitment: [u8; 32],
+    pub recipient: Pubkey,
+    pub relayer: Pubkey,
+    /// `hash_splits` of the payees sharing the recipient's part
+    pub splits_hash: [u8; 32],
+    /// `deposit_amount` of the pool, the most a note can be worth
+    pub denomination: u64,
+    /// Taken out of the pool, `fee` and the pool's costs included
+    pub amount: u64,
+    pub fee: u64,
+    pub refund: u64,
+    /// unix timestamp after which `withdraw` rejects the proof, 0 for no expiry
+    pub expires_at: i64,
+}
+
+impl PublicInputs {
+    pub const LEN: usize = 2 + 32 + 4 + 32 + 32 + 32 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 8;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = header(ProofKind::Withdraw, Self::LEN);
+        out.extend_from_slice(&self.domain);
+        out.extend_from_slice(&self.tree_index.to_le_bytes());
+        out.extend_from_slice(&self.root);
+        out.extend_from_slice(&self.nullifier_hash);
+        out.extend_from_slice(&self.change_commitment);
+        out.extend_from_slice(self.recipient.as_ref());
+        out.extend_from_slice(self.relayer.as_ref());
+        out.extend_from_slice(&self.splits_hash);
+        out.extend_from_slice(&self.denomination.to_le_bytes());
+        out.extend_from_slice(&self.amount.to_le_bytes());
+        out.extend_from_slice(&self.fee.to_le_bytes());
+        out.extend_from_slice(&self.refund.to_le_bytes());
+        out.extend_from_slice(&self.expires_at.to_le_bytes());
+        out
+    }
+
+    /// Rejects unknown versions, other kinds and any length other than `LEN`
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes, ProofKind::Withdraw, Self::LEN)?;
+        Ok(PublicInputs {
+            domain: reader.take(),
+            tree_index: u32::from_le_bytes(reader.take()),
+            root: reader.take(),
+            nullifier_hash: reader.take(),
+            change_commitment: reader.take(),
+            recipient: Pubkey::new_from_array(reader.take()),
+            relayer: Pubkey::new_from_array(reader.take()),
+            splits_hash: reader.take(),
+            denomination: u64::from_le_bytes(reader.take()),
+            amount: u64::from_le_bytes(reader.take()),
+            fee: u64::from_le_bytes(reader.take()),
+            refund: u64::from_le_bytes(reader.take()),
+            expires_at: i64::from_le_bytes(reader.take()),
+        })
+    }
+}
+
+const SPLITS_SEPARATOR: &[u8] = b"solana-mixer/splits/v1";
+
+/// One payee of a split withdrawal
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WithdrawSplit {
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+/// The `splits_hash` a withdrawal commits to: sha256 of a fixed prefix and
+/// each payee's `recipient || amount (LE)`, in payout order
+pub fn hash_splits(splits: &[WithdrawSplit]) -> [u8; 32] {
+    let mut data = Vec::with_capacity(splits.len() * 40);
+    for split in splits {
+        data.extend_from_slice(split.recipient.as_ref());
+        data.extend_from_slice(&split.amount.to_le_bytes());
+    }
+    hashv(&[SPLITS_SEPARATOR, &data]).to_bytes()
+}
+
+/// Values committed by a private transfer proof of a fixed-amount pool: the
+/// note behind `nullifier_hash` is replaced by `new_commitment`, of the same
+/// value, for the recipient
+///
+/// ```text
+/// version          1
+/// kind             1  ProofKind::Transfer
+/// domain          32
+/// tree_index       4  LE
+/// root            32
+/// nullifier_hash  32
+/// new_commitment  32
+/// expires_at       8  LE
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransferInputs {
+    /// `domain_tag` of the pool the proof is for
+    pub domain: [u8; 32],
+    /// `MerkleTree` of the pool that `root` belongs to
+    pub tree_index: u32,
+    pub root: [u8; 32],
+    pub nullifier_hash: [u8; 32],
+    pub new_commitment: [u8; 32],
+    /// unix timestamp after which `private_transfer` rejects the proof, 0 for no expiry
+    pub expires_at: i64,
+}
+
+impl TransferInputs {
+    pub const LEN: usize = 2 + 32 + 4 + 32 + 32 + 32 + 8;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = header(ProofKind::Transfer, Self::LEN);
+        out.extend_from_slice(&self.domain);
+        out.extend_from_slice(&self.tree_index.to_le_bytes());
+        out.extend_from_slice(&self.root);
+        out.extend_from_slice(&self.nullifier_hash);
+        out.extend_from_slice(&self.new_commitment);
+        out.extend_from_slice(&self.expires_at.to_le_bytes());
+        out
+    }
+
+    /// Rejects unknown versions, other kinds and any length other than `LEN`
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes, ProofKind::Transfer, Self::LEN)?;
+        Ok(TransferInputs {
+            domain: reader.take(),
+            tree_index: u32::from_le_bytes(reader.take()),
+            root: reader.take(),
+            nullifier_hash: reader.take(),
+            new_commitment: reader.take(),
+            expires_at: i64::from_le_bytes(reader.take()),
+        })
+    }
+}
+
+/// Values committed by a join-split proof of a shielded pool (`transact`):
+///
+/// ```text
+/// version              1
+/// kind                 1  ProofKind::Transact
+/// domain              32
+/// tree_index           4  LE
+/// root                32
+/// input_nullifiers    64  2 x 32, all zero for an unused input
+/// output_commitments  64  2 x 32, all zero for an unused output
+/// ext_amount           8  LE, signed
+/// fee                  8  LE
+/// recipient           32
+/// relayer             32
+/// expires_at           8  LE
+/// ```
+///
+/// The circuit proves the inputs are notes under `root` and that
+/// `sum(inputs) + ext_amount == sum(outputs) + fee`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransactInputs {
+    /// `domain_tag` of the pool the proof is for
+    pub domain: [u8; 32],
+    /// `MerkleTree` of the pool that `root` belongs to
+    pub tree_index: u32,
+    pub root: [u8; 32],
+    pub input_nullifiers: [[u8; 32]; 2],
+    pub output_commitments: [[u8; 32]; 2],
+    /// Value entering the pool from the caller (> 0) or leaving it to
+    /// `recipient` (< 0)
+    pub ext_amount: i64,
+    /// Paid to `relayer` out of the pool
+    pub fee: u64,
+    pub recipient: Pubkey,
+    pub relayer: Pubkey,
+    /// unix timestamp after which `transact` rejects the proof, 0 for no expiry
+    pub expires_at: i64,
+}
+
+impl TransactInputs {
+    pub const LEN: usize = 2 + 32 + 4 + 32 + 32 * 2 + 32 * 2 + 8 + 8 + 32 + 32 + 8;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = header(ProofKind::Transact, Self::LEN);
+        out.extend_from_slice(&self.domain);
+        out.extend_from_slice(&self.tree_index.to_le_bytes());
+        out.extend_from_slice(&self.root);
+        for nullifier in &self.input_nullifiers {
+            out.extend_from_slice(nullifier);
+        }
+        for commitment in &self.output_commitments {
+            out.extend_from_slice(commitment);
+        }
+        out.extend_from_slice(&self.ext_amount.to_le_bytes());
+        out.extend_from_slice(&self.fee.to_le_bytes());
+        out.extend_from_slice(self.recipient.as_ref());
+        out.extend_from_slice(self.relayer.as_ref());
+        out.extend_from_slice(&self.expires_at.to_le_bytes());
+        out
+    }
+
+    /// Rejects unknown versions, other kinds and any length other than `LEN`
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes, ProofKind::Transact, Self::LEN)?;
+        Ok(TransactInputs {
+            domain: reader.take(),
+            tree_index: u32::from_le_bytes(reader.take()),
+            root: reader.take(),
+            input_nullifiers: [reader.take(), reader.take()],
+            output_commitments: [reader.take(), reader.take()],
+            ext_amount: i64::from_le_bytes(reader.take()),
+            fee: u64::from_le_bytes(reader.take()),
+            recipient: Pubkey::new_from_array(reader.take()),
+            relayer: Pubkey::new_from_array(reader.take()),
+            expires_at: i64::from_le_bytes(reader.take()),
+        })
+    }
+}
+
+fn header(kind: ProofKind, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    out.push(PUBLIC_INPUTS_VERSION);
+    out.push(kind as u8);
+    out
+}
+
+/// Walks a buffer whose length has already been checked
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Check length, version and kind, leaving the reader after the header
+    fn new(bytes: &'a [u8], kind: ProofKind, len: usize) -> Result<Self> {
+        require!(bytes.len() == len, ErrorCode::InvalidInput);
+        require!(
+            bytes[0] == PUBLIC_INPUTS_VERSION,
+            ErrorCode::UnsupportedPublicInputsVersion
+        );
+        require!(bytes[1] == kind as u8, ErrorCode::InvalidInput);
+        Ok(Reader(&bytes[2..]))
+    }
+
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        head.try_into().unwrap()
+    }
+}
@@ -0,0 +1,55 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::{
+    self, get_associated_token_address_with_program_id, AssociatedToken, Create,
+};
+use anchor_spl::token_interface::{Mint, TokenInterface};
+
+use crate::ErrorCode;
+
+/// Paying `amount` lamports into `account` must leave it rent exempt, which a
+/// brand-new wallet only is once it holds the minimum for an empty account.
+/// Checked up front so a short payout fails with `RecipientNotRentExempt`
+/// rather than an opaque runtime error.
+pub fn check_rent_exempt(account: &AccountInfo, amount: u64) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let balance = account
+        .lamports()
+        .checked_add(amount)
+        .ok_or(error!(ErrorCode::MathError))?;
+    require!(
+        Rent::get()?.is_exempt(balance, account.data_len()),
+        ErrorCode::RecipientNotRentExempt
+    );
+    Ok(())
+}
+
+/// Create `owner`'s associated token account for `mint` unless it exists
+/// already, `payer` covering the rent
+pub fn create_recipient_ata<'info>(
+    ata: &AccountInfo<'info>,
+    owner: &AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    payer: &AccountInfo<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    ata_program: &Program<'info, AssociatedToken>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    require_keys_eq!(
+        *ata.key,
+        get_associated_token_address_with_program_id(owner.key, &mint.key(), &token_program.key()),
+        ErrorCode::InvalidInput
+    );
+    associated_token::create_idempotent(CpiContext::new(
+        ata_program.to_account_info(),
+        Create {
+            payer: payer.clone(),
+            associated_token: ata.clone(),
+            authority: owner.clone(),
+            mint: mint.to_account_info(),
+            system_program: system_program.to_account_info(),
+            token_program: token_program.to_account_info(),
+        },
+    ))
+}
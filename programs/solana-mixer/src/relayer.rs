@@ -0,0 +1,63 @@
+use anchor_lang::prelude::*;
+
+use crate::{ErrorCode, State};
+
+/// Longest metadata URL a relayer can register
+pub const MAX_RELAYER_URL_LEN: usize = 200;
+
+/// Seconds between `deregister_relayer` and `withdraw_stake`, so the
+/// administrator can still slash a relayer that misbehaved just before leaving
+pub const RELAYER_UNBONDING_PERIOD: i64 = 7 * 24 * 60 * 60;
+
+/// A relayer's registration with one pool, at `[RELAYER_SEED, pool, relayer]`.
+/// The account also holds the relayer's stake, topped up by `top_up_stake` and
+/// paid back by `withdraw_stake`
+/// once the relayer has deregistered and `RELAYER_UNBONDING_PERIOD` has passed.
+#[account]
+#[derive(Debug)]
+pub struct RelayerEntry {
+    pub pool: Pubkey,
+    pub relayer: Pubkey,
+    /// Highest `fee` this relayer may take, on top of the pool's own cap
+    pub max_fee: u64,
+    /// Lamports staked at registration, over and above the rent
+    pub stake: u64,
+    /// Set by the pool administrator, stands in for the stake
+    pub approved: bool,
+    /// unix timestamp of `deregister_relayer`, `None` while registered
+    pub deregistered_at: Option<i64>,
+    /// Where wallets find the relayer's API and terms
+    pub url: String,
+}
+
+impl RelayerEntry {
+    pub const SPACE: usize = 8 + 32 + 32 + 8 + 8 + 1 + (1 + 8) + 4 + MAX_RELAYER_URL_LEN;
+
+    /// Whether the relayer may take fees under the pool's current `min_stake`
+    pub fn is_active(&self, min_stake: u64) -> bool {
+        self.deregistered_at.is_none()
+            && (self.approved || (min_stake > 0 && self.stake >= min_stake))
+    }
+
+    /// When `withdraw_stake` opens, `None` while still registered
+    pub fn unbonded_at(&self) -> Option<i64> {
+        self.deregistered_at
+            .map(|at| at.saturating_add(RELAYER_UNBONDING_PERIOD))
+    }
+}
+
+/// A relayer cannot take more than the pool's cap, and in pools that only pay
+/// registered relayers not more than its own, nor can an unknown account take
+/// a fee at all
+pub fn check_relayer_fee(state: &State, fee: u64, entry: Option<&RelayerEntry>) -> Result<()> {
+    require!(fee <= state.max_relayer_fee, ErrorCode::FeeTooHigh);
+    if state.registered_relayers_only != 0 && fee > 0 {
+        let entry = entry.ok_or(error!(ErrorCode::RelayerNotRegistered))?;
+        require!(
+            entry.is_active(state.min_relayer_stake),
+            ErrorCode::RelayerNotRegistered
+        );
+        require!(fee <= entry.max_fee, ErrorCode::FeeTooHigh);
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{
+    self,
+    spl_token_2022::{
+        self,
+        extension::{
+            transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType,
+            StateWithExtensions,
+        },
+    },
+    Mint, TokenAccount, TokenInterface, TransferChecked,
+};
+
+use crate::ErrorCode;
+
+/// Token-2022 extensions a vault cannot live with: transfer hooks need extra
+/// accounts the mixer never passes, a permanent delegate could drain the vault
+/// and non-transferable tokens could never be withdrawn.
+const UNSUPPORTED_EXTENSIONS: [ExtensionType; 3] = [
+    ExtensionType::TransferHook,
+    ExtensionType::PermanentDelegate,
+    ExtensionType::NonTransferable,
+];
+
+pub fn check_mint_supported(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+    let data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    let extensions = mint_state.get_extension_types()?;
+    require!(
+        !extensions
+            .iter()
+            .any(|e| UNSUPPORTED_EXTENSIONS.contains(e)),
+        ErrorCode::UnsupportedMint
+    );
+    Ok(())
+}
+
+/// Transfer fee withheld by the mint so that exactly `net` tokens arrive
+fn inverse_transfer_fee(mint: &AccountInfo, net: u64) -> Result<u64> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(0);
+    }
+    let data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(config) => config
+            .calculate_inverse_epoch_fee(Clock::get()?.epoch, net)
+            .ok_or(error!(ErrorCode::MathError)),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Pull `net` tokens into the vault; the depositor covers any transfer fee so
+/// every note in the pool is backed by exactly `deposit_amount`.
+pub fn deposit_tokens<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    vault: &mut InterfaceAccount<'info, TokenAccount>,
+    authority: AccountInfo<'info>,
+    net: u64,
+) -> Result<()> {
+    let gross = net
+        .checked_add(inverse_transfer_fee(&mint.to_account_info(), net)?)
+        .ok_or(error!(ErrorCode::MathError))?;
+    let before = vault.amount;
+
+    token_interface::transfer_checked(
+        CpiContext::new(
+            token_program.to_account_info(),
+            TransferChecked {
+                from: from.to_account_info(),
+                mint: mint.to_account_info(),
+                to: vault.to_account_info(),
+                authority,
+            },
+        ),
+        gross,
+        mint.decimals,
+    )?;
+
+    vault.reload()?;
+    let received = vault
+        .amount
+        .checked_sub(before)
+        .ok_or(error!(ErrorCode::MathError))?;
+    require!(received == net, ErrorCode::DepositAmountMismatch);
+    Ok(())
+}
+
+/// Pay `amount` out of the vault, signed by the pool PDA. Any Token-2022
+/// transfer fee is withheld from what `to` receives.
+pub fn pay_tokens<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    vault: &InterfaceAccount<'info, TokenAccount>,
+    to: AccountInfo<'info>,
+    pool: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            TransferChecked {
+                from: vault.to_account_info(),
+                mint: mint.to_account_info(),
+                to,
+                authority: pool,
+            },
+            signer_seeds,
+        ),
+        amount,
+        mint.decimals,
+    )
+}
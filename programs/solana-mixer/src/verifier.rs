@@ -0,0 +1,214 @@
+use anchor_lang::prelude::*;
+// the enum derives below expand to `borsh::...`, which the prelude glob and
+// our own `borsh` dependency would otherwise both claim
+use anchor_lang::prelude::borsh;
+use anchor_lang::solana_program::hash::hashv;
+use solana_bn254::prelude::{alt_bn128_addition, alt_bn128_multiplication, alt_bn128_pairing};
+
+use crate::ErrorCode;
+
+const GROTH16_VK_4_0_0_RC3_BYTES: &[u8] = &sp1_solana::GROTH16_VK_4_0_0_RC3_BYTES;
+
+/// BN254 base field modulus, big endian
+const BN254_FIELD_MODULUS: [u8; 32] = [
+    48, 100, 78, 114, 225, 49, 160, 41, 184, 80, 69, 182, 129, 129, 88, 93, 151, 129, 106, 145,
+    104, 113, 202, 141, 60, 32, 140, 22, 216, 124, 253, 71,
+];
+
+/// Proving system a pool accepts withdrawal proofs from. Stored in `State` as
+/// its `u8` discriminant.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum VerifierKind {
+    /// SP1 program wrapped in SP1's Groth16 circuit, `vkey_hash` is the SP1 program vkey hash
+    #[default]
+    Sp1Groth16,
+    /// circom/snarkjs Groth16 proof checked with the alt_bn128 syscalls,
+    /// `vkey_hash` is `CircomVerifyingKey::hash`
+    CircomGroth16,
+    /// Test backend, only usable when built with the `mock-verifier` feature
+    Mock,
+}
+
+impl TryFrom<u8> for VerifierKind {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(VerifierKind::Sp1Groth16),
+            1 => Ok(VerifierKind::CircomGroth16),
+            2 => Ok(VerifierKind::Mock),
+            _ => err!(ErrorCode::VerifierUnavailable),
+        }
+    }
+}
+
+pub trait ProofVerifier {
+    /// Check that `proof` attests to `public_inputs` for the circuit identified by `vkey_hash`
+    fn verify(&self, proof: &[u8], public_inputs: &[u8], vkey_hash: &[u8; 32]) -> Result<()>;
+}
+
+/// Run the backend `kind`. `circom_vk` is only read by `CircomGroth16`.
+pub fn verify_proof(
+    kind: VerifierKind,
+    circom_vk: Option<&CircomVerifyingKey>,
+    proof: &[u8],
+    public_inputs: &[u8],
+    vkey_hash: &[u8; 32],
+) -> Result<()> {
+    match kind {
+        VerifierKind::Sp1Groth16 => Sp1Groth16.verify(proof, public_inputs, vkey_hash),
+        VerifierKind::CircomGroth16 => {
+            let vk = circom_vk.ok_or(error!(ErrorCode::MissingVerifyingKey))?;
+            CircomGroth16 { vk }.verify(proof, public_inputs, vkey_hash)
+        }
+        #[cfg(feature = "mock-verifier")]
+        VerifierKind::Mock => MockVerifier.verify(proof, public_inputs, vkey_hash),
+        #[cfg(not(feature = "mock-verifier"))]
+        VerifierKind::Mock => err!(ErrorCode::VerifierUnavailable),
+    }
+}
+
+pub struct Sp1Groth16;
+
+impl ProofVerifier for Sp1Groth16 {
+    fn verify(&self, proof: &[u8], public_inputs: &[u8], vkey_hash: &[u8; 32]) -> Result<()> {
+        sp1_solana::verify_proof(
+            proof,
+            public_inputs,
+            &vkey_hash_hex(vkey_hash),
+            GROTH16_VK_4_0_0_RC3_BYTES,
+        )
+        .map_err(|_| error!(ErrorCode::InvalidProof))
+    }
+}
+
+/// `sp1_solana::verify_proof` takes the vkey hash as a 0x-prefixed hex string
+fn vkey_hash_hex(hash: &[u8; 32]) -> String {
+    let mut out = String::with_capacity(66);
+    out.push_str("0x");
+    for b in hash {
+        out.push_str(&format!("{:02x}", b));
+    }
+    out
+}
+
+/// Groth16 verifying key exported by snarkjs, points in the big-endian
+/// encoding the alt_bn128 syscalls take (G2 coordinates as c1 || c0).
+///
+/// Circuits have one public signal: sha256(public_inputs) with the top three
+/// bits cleared, the same digest SP1 commits to, so the public-input layout is
+/// shared by every backend.
+#[account]
+#[derive(Debug)]
+pub struct CircomVerifyingKey {
+    pub alpha_g1: [u8; 64],
+    pub beta_g2: [u8; 128],
+    pub gamma_g2: [u8; 128],
+    pub delta_g2: [u8; 128],
+    pub ic: [[u8; 64]; 2],
+}
+
+impl CircomVerifyingKey {
+    pub const SPACE: usize = 8 + 64 + 128 * 3 + 64 * 2;
+
+    /// Value stored as the pool's `vkey_hash` for this key
+    pub fn hash(&self) -> [u8; 32] {
+        hashv(&[
+            &self.alpha_g1,
+            &self.beta_g2,
+            &self.gamma_g2,
+            &self.delta_g2,
+            &self.ic[0],
+            &self.ic[1],
+        ])
+        .to_bytes()
+    }
+}
+
+pub struct CircomGroth16<'a> {
+    pub vk: &'a CircomVerifyingKey,
+}
+
+impl ProofVerifier for CircomGroth16<'_> {
+    /// `proof` is A (64) || B (128) || C (64)
+    fn verify(&self, proof: &[u8], public_inputs: &[u8], vkey_hash: &[u8; 32]) -> Result<()> {
+        require!(self.vk.hash() == *vkey_hash, ErrorCode::InvalidProof);
+        require!(proof.len() == 256, ErrorCode::InvalidProof);
+        let bn_err = |_| error!(ErrorCode::InvalidProof);
+
+        // vk_x = IC[0] + IC[1] * input
+        let input = public_inputs_digest(public_inputs);
+        let scaled =
+            alt_bn128_multiplication(&[&self.vk.ic[1][..], &input[..]].concat()).map_err(bn_err)?;
+        let vk_x =
+            alt_bn128_addition(&[&self.vk.ic[0][..], &scaled[..]].concat()).map_err(bn_err)?;
+
+        // e(-A, B) * e(alpha, beta) * e(vk_x, gamma) * e(C, delta) == 1
+        let pairing_input = [
+            &negate_g1(&proof[0..64])[..],
+            &proof[64..192],
+            &self.vk.alpha_g1,
+            &self.vk.beta_g2,
+            &vk_x,
+            &self.vk.gamma_g2,
+            &proof[192..256],
+            &self.vk.delta_g2,
+        ]
+        .concat();
+        let result = alt_bn128_pairing(&pairing_input).map_err(bn_err)?;
+        require!(
+            result.len() == 32 && result[31] == 1 && result[..31].iter().all(|b| *b == 0),
+            ErrorCode::InvalidProof
+        );
+        Ok(())
+    }
+}
+
+/// sha256 of the public inputs, masked below the BN254 scalar field
+pub fn public_inputs_digest(public_inputs: &[u8]) -> [u8; 32] {
+    let mut digest = hashv(&[public_inputs]).to_bytes();
+    digest[0] &= 0x1f;
+    digest
+}
+
+/// (x, y) -> (x, q - y); the point at infinity, all zero, is its own negation
+pub fn negate_g1(point: &[u8]) -> [u8; 64] {
+    let mut out = [0u8; 64];
+    out[..32].copy_from_slice(&point[..32]);
+    let y = &point[32..64];
+    if y.iter().all(|b| *b == 0) {
+        return out;
+    }
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let mut d = BN254_FIELD_MODULUS[i] as i16 - y[i] as i16 - borrow;
+        borrow = 0;
+        if d < 0 {
+            d += 256;
+            borrow = 1;
+        }
+        out[32 + i] = d as u8;
+    }
+    out
+}
+
+/// Accepts exactly the proofs produced by `mock_proof`
+#[cfg(feature = "mock-verifier")]
+pub struct MockVerifier;
+
+#[cfg(feature = "mock-verifier")]
+impl ProofVerifier for MockVerifier {
+    fn verify(&self, proof: &[u8], public_inputs: &[u8], vkey_hash: &[u8; 32]) -> Result<()> {
+        require!(
+            proof == mock_proof(vkey_hash, public_inputs),
+            ErrorCode::InvalidProof
+        );
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mock-verifier")]
+pub fn mock_proof(vkey_hash: &[u8; 32], public_inputs: &[u8]) -> Vec<u8> {
+    hashv(&[vkey_hash, public_inputs]).to_bytes().to_vec()
+}
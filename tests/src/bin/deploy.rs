@@ -6,11 +6,15 @@ use anchor_client::solana_sdk::{
 };
 use anchor_client::{Client, Cluster, Program};
 use anyhow::Context;
+use solana_mixer::{DEFAULT_ROOT_HISTORY_SIZE, DEFAULT_TREE_DEPTH, NATIVE_MINT};
 use std::process::{Command, Stdio};
 use std::rc::Rc;
 use std::str::FromStr;
+use tests::utils::{pool_address, treasury_address, tree_address};
 
 const PROGRAM_ID: &str = "AQW933TrdFxE5q7982Vb57crHjZe3B7EZaHotdXnaQYQ";
+/// SOL pools created on deploy: 0.1, 1, 10 and 100 SOL
+const DENOMINATIONS: [u64; 4] = [100_000_000, 1_000_000_000, 10_000_000_000, 100_000_000_000];
 
 fn main() -> anyhow::Result<()> {
     let wallet_path = std::env::var("ANCHOR_WALLET")
@@ -51,22 +55,34 @@ fn main() -> anyhow::Result<()> {
     );
     let program: Program<Rc<Keypair>> = client.program(program_id).unwrap();
 
-    let (state_pda, _bump) = Pubkey::find_program_address(&[b"mixer_state"], &program_id);
+    let cluster_genesis_hash = program.rpc().get_genesis_hash()?.to_bytes();
 
-    let sig = program
-        .request()
-        .accounts(solana_mixer::accounts::Initialize {
-            state: state_pda,
-            admin: payer.pubkey(),
-            system_program: system_program::ID,
-        })
-        .args(solana_mixer::instruction::Initialize {
-            deposit_amount: 1_000_000_000, //1 SOL
-        })
-        .signer(&*payer)
-        .send()
-        .expect("Not able to send transaction Initialize");
+    for deposit_amount in DENOMINATIONS {
+        let state_pda = pool_address(&NATIVE_MINT, deposit_amount);
 
-    println!("🔧 initialize tx signature: {}", sig);
+        let sig = program
+            .request()
+            .accounts(solana_mixer::accounts::Initialize {
+                state: state_pda,
+                tree: tree_address(&state_pda, 0),
+                treasury: treasury_address(&state_pda),
+                admin: payer.pubkey(),
+                system_program: system_program::ID,
+            })
+            .args(solana_mixer::instruction::Initialize {
+                deposit_amount,
+                cluster_genesis_hash,
+                tree_depth: DEFAULT_TREE_DEPTH,
+                root_history_size: DEFAULT_ROOT_HISTORY_SIZE,
+            })
+            .signer(&*payer)
+            .send()
+            .expect("Not able to send transaction Initialize");
+
+        println!(
+            "🔧 initialize {} lamport pool {} tx signature: {}",
+            deposit_amount, state_pda, sig
+        );
+    }
     Ok(())
 }
@@ -1,6 +1,37 @@
 pub mod merkle;
+pub mod note;
 pub mod off_proof;
 #[cfg(test)]
+mod svm;
+#[cfg(test)]
+mod test_admin;
+#[cfg(test)]
+mod test_compute_units;
+#[cfg(test)]
+mod test_deposit;
+#[cfg(test)]
+mod test_merkle_tree;
+#[cfg(test)]
 mod test_mixer;
+#[cfg(test)]
+mod test_note;
+#[cfg(test)]
+mod test_private_transfer;
+#[cfg(test)]
+mod test_protocol_fee;
+#[cfg(test)]
+mod test_public_inputs;
+#[cfg(test)]
+mod test_relayer;
+#[cfg(test)]
+mod test_token_pool;
+#[cfg(test)]
+mod test_transact;
+#[cfg(test)]
+mod test_tree;
+#[cfg(test)]
+mod test_verifier;
+#[cfg(test)]
+mod test_withdraw;
 
 pub mod utils;
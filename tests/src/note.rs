@@ -0,0 +1,161 @@
+//! Client side of the encrypted note a deposit can attach to its `DepositEvent`.
+//!
+//! A note is encrypted to the owner's X25519 viewing key with a fresh ephemeral
+//! key per deposit:
+//!
+//! ```text
+//! ephemeral_pubkey  32
+//! ciphertext        72 + 16   ChaCha20-Poly1305 of nullifier || secret || value (LE)
+//! ```
+//!
+//! The key is `sha256(KEY_DOMAIN || shared_secret || ephemeral_pubkey || viewing_pubkey)`.
+//! As it is never reused the nonce is all zeros. The commitment is the
+//! associated data, so a blob copied onto someone else's deposit does not decrypt.
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use solana_mixer::DepositEvent;
+use std::error::Error;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::utils::fetch_deposit_events;
+
+const KEY_DOMAIN: &[u8] = b"solana-mixer/note/v1";
+const PLAINTEXT_LEN: usize = 32 + 32 + 8;
+pub const ENCRYPTED_NOTE_LEN: usize = 32 + PLAINTEXT_LEN + 16;
+
+/// What is needed to withdraw a deposit: the nullifier and secret as
+/// little-endian field elements, and the value the commitment holds, which is
+/// less than the deposit amount for the change of a partial withdrawal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    pub nullifier: [u8; 32],
+    pub secret: [u8; 32],
+    pub value: u64,
+}
+
+/// A note found on chain, with where its commitment sits
+#[derive(Debug, Clone)]
+pub struct RecoveredNote {
+    pub note: Note,
+    pub commitment: [u8; 32],
+    pub tree_index: u32,
+    pub leaf_index: u32,
+}
+
+/// Secret half of a viewing key; share `public()` with whoever deposits for you
+pub struct ViewingKey(StaticSecret);
+
+impl ViewingKey {
+    pub fn generate() -> Self {
+        ViewingKey(StaticSecret::random_from_rng(OsRng))
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        ViewingKey(StaticSecret::from(bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    pub fn public(&self) -> PublicKey {
+        PublicKey::from(&self.0)
+    }
+
+    /// Open `encrypted_note`, `None` if it was not made for this key and commitment
+    pub fn decrypt(&self, commitment: &[u8; 32], encrypted_note: &[u8]) -> Option<Note> {
+        if encrypted_note.len() != ENCRYPTED_NOTE_LEN {
+            return None;
+        }
+        let (ephemeral, ciphertext) = encrypted_note.split_at(32);
+        let ephemeral = PublicKey::from(<[u8; 32]>::try_from(ephemeral).ok()?);
+        let shared = self.0.diffie_hellman(&ephemeral);
+        let plaintext = cipher(shared.as_bytes(), &ephemeral, &self.public())
+            .decrypt(
+                Nonce::from_slice(&[0u8; 12]),
+                Payload {
+                    msg: ciphertext,
+                    aad: commitment,
+                },
+            )
+            .ok()?;
+        let (nullifier, rest) = plaintext.split_at(32);
+        let (secret, value) = rest.split_at(32);
+        Some(Note {
+            nullifier: nullifier.try_into().ok()?,
+            secret: secret.try_into().ok()?,
+            value: u64::from_le_bytes(value.try_into().ok()?),
+        })
+    }
+}
+
+/// Encrypt `note` to `viewing_key`, giving the `encrypted_note` for `deposit`
+pub fn encrypt_note(viewing_key: &PublicKey, commitment: &[u8; 32], note: &Note) -> Vec<u8> {
+    let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral = PublicKey::from(&ephemeral_secret);
+    let shared = ephemeral_secret.diffie_hellman(viewing_key);
+
+    let mut plaintext = [0u8; PLAINTEXT_LEN];
+    plaintext[..32].copy_from_slice(&note.nullifier);
+    plaintext[32..64].copy_from_slice(&note.secret);
+    plaintext[64..].copy_from_slice(&note.value.to_le_bytes());
+    let ciphertext = cipher(shared.as_bytes(), &ephemeral, viewing_key)
+        .encrypt(
+            Nonce::from_slice(&[0u8; 12]),
+            Payload {
+                msg: &plaintext,
+                aad: commitment,
+            },
+        )
+        .expect("encrypting a 72 byte note");
+
+    let mut out = Vec::with_capacity(ENCRYPTED_NOTE_LEN);
+    out.extend_from_slice(ephemeral.as_bytes());
+    out.extend_from_slice(&ciphertext);
+    out
+}
+
+fn cipher(shared: &[u8; 32], ephemeral: &PublicKey, viewing_key: &PublicKey) -> ChaCha20Poly1305 {
+    let key: [u8; 32] = Sha256::new()
+        .chain_update(KEY_DOMAIN)
+        .chain_update(shared)
+        .chain_update(ephemeral.as_bytes())
+        .chain_update(viewing_key.as_bytes())
+        .finalize()
+        .into();
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+/// Scan the deposits of `pool` for notes encrypted to `viewing_key`
+pub fn scan_notes(
+    pool: Pubkey,
+    viewing_key: &ViewingKey,
+) -> Result<Vec<RecoveredNote>, Box<dyn Error>> {
+    Ok(recover_notes(fetch_deposit_events(pool)?, viewing_key))
+}
+
+/// The notes among `events` encrypted to `viewing_key`, in tree order
+pub fn recover_notes(
+    events: impl IntoIterator<Item = DepositEvent>,
+    viewing_key: &ViewingKey,
+) -> Vec<RecoveredNote> {
+    let mut notes: Vec<RecoveredNote> = events
+        .into_iter()
+        .filter_map(|event| {
+            let note = viewing_key.decrypt(&event.commitment, event.encrypted_note.as_deref()?)?;
+            Some(RecoveredNote {
+                note,
+                commitment: event.commitment,
+                tree_index: event.tree_index,
+                leaf_index: event.leaf_index,
+            })
+        })
+        .collect();
+    notes.sort_unstable_by_key(|n| (n.tree_index, n.leaf_index));
+    notes
+}
@@ -0,0 +1,379 @@
+//! LiteSVM harness for the instruction tests. They run against a build of the
+//! program with the `mock-verifier` feature, so proofs are just
+//! `verifier::mock_proof` and no prover is needed:
+//!
+//! ```text
+//! anchor build -- --features mock-verifier
+//! cp target/deploy/solana_mixer.so target/deploy/solana_mixer_mock.so
+//! anchor build
+//! ```
+//!
+//! or point `MIXER_MOCK_SO` at the mock build.
+use std::path::{Path, PathBuf};
+
+use anchor_client::{
+    anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas},
+    solana_sdk::{
+        clock::Clock,
+        instruction::{Instruction, InstructionError},
+        pubkey::Pubkey,
+        signature::Keypair,
+        signer::Signer,
+        system_program,
+        transaction::{Transaction, TransactionError},
+    },
+};
+use litesvm::{types::TransactionResult, LiteSVM};
+use solana_mixer::{
+    domain_tag, merkle_tree::TreeRef, verifier::mock_proof, PublicInputs, State, VerifierKind,
+    DEFAULT_ROOT_HISTORY_SIZE, DEFAULT_TREE_DEPTH, NATIVE_MINT,
+};
+
+use crate::utils::{
+    deposit_accounts, hash_pair, pool_address, treasury_address, tree_address, vault_address,
+    withdraw_accounts,
+};
+
+/// Passed to `initialize` as the cluster's genesis hash
+pub const GENESIS_HASH: [u8; 32] = [7u8; 32];
+/// Vkey hash the pools are rotated to; `mock_proof` binds it like a real vkey
+pub const MOCK_VKEY_HASH: [u8; 32] = [0x4d; 32];
+/// 1 SOL
+pub const DEPOSIT_AMOUNT: u64 = 1_000_000_000;
+
+/// The mock-verifier program, failing with build instructions when it is missing
+pub fn mock_program() -> PathBuf {
+    let path = std::env::var_os("MIXER_MOCK_SO")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../target/deploy/solana_mixer_mock.so")
+        });
+    assert!(
+        path.exists(),
+        "{} not found: run `anchor build -- --features mock-verifier` and copy \
+         target/deploy/solana_mixer.so there, or set MIXER_MOCK_SO",
+        path.display()
+    );
+    path
+}
+
+/// The program as deployed, without the mock verifier (`anchor build`)
+pub fn production_program() -> PathBuf {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../target/deploy/solana_mixer.so");
+    assert!(
+        path.exists(),
+        "{} not found: run `anchor build`",
+        path.display()
+    );
+    path
+}
+
+/// A distinct valid commitment per `seed`; any Poseidon output is a field element
+pub fn commitment(seed: u8) -> [u8; 32] {
+    hash_pair(&[seed; 32], &[seed; 32])
+}
+
+/// Proof the mock verifier accepts for `public_inputs`
+pub fn prove(public_inputs: &[u8]) -> Vec<u8> {
+    mock_proof(&MOCK_VKEY_HASH, public_inputs)
+}
+
+/// Instruction of the mixer program
+pub fn ix(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: solana_mixer::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+/// Fail unless `result` is the program error `code`
+#[track_caller]
+pub fn assert_error(result: TransactionResult, code: impl Into<u32> + std::fmt::Debug + Copy) {
+    match result {
+        Ok(_) => panic!("expected {code:?}, the transaction succeeded"),
+        Err(failed) => match failed.err {
+            TransactionError::InstructionError(_, InstructionError::Custom(found))
+                if found == code.into() => {}
+            err => panic!("expected {code:?}, got {err:?}\n{:#?}", failed.meta.logs),
+        },
+    }
+}
+
+/// One pool in a fresh LiteSVM, with the administrator and a funded user that
+/// deposits and submits withdrawals
+pub struct Mixer {
+    pub svm: LiteSVM,
+    pub admin: Keypair,
+    pub user: Keypair,
+    pub pool: Pubkey,
+    pub mint: Pubkey,
+    pub deposit_amount: u64,
+}
+
+impl Mixer {
+    /// No pool yet; see `init_sol_pool` / `init_token_pool`
+    pub fn new() -> Self {
+        Self::with_program(&mock_program())
+    }
+
+    pub fn with_program(program: &Path) -> Self {
+        let mut svm = LiteSVM::new();
+        svm.add_program_from_file(solana_mixer::ID, program)
+            .expect("load program");
+        let admin = Keypair::new();
+        let user = Keypair::new();
+        svm.airdrop(&admin.pubkey(), 1_000 * DEPOSIT_AMOUNT)
+            .unwrap();
+        svm.airdrop(&user.pubkey(), 1_000 * DEPOSIT_AMOUNT).unwrap();
+        Mixer {
+            svm,
+            admin,
+            user,
+            pool: Pubkey::default(),
+            mint: NATIVE_MINT,
+            deposit_amount: 0,
+        }
+    }
+
+    /// SOL pool of `deposit_amount` notes with the default tree, on the mock verifier
+    pub fn sol_pool(deposit_amount: u64) -> Self {
+        let mut mixer = Self::new();
+        mixer
+            .init_sol_pool(
+                deposit_amount,
+                DEFAULT_TREE_DEPTH,
+                DEFAULT_ROOT_HISTORY_SIZE,
+            )
+            .unwrap();
+        mixer
+    }
+
+    pub fn init_sol_pool(
+        &mut self,
+        deposit_amount: u64,
+        tree_depth: u8,
+        root_history_size: u16,
+    ) -> TransactionResult {
+        self.pool = pool_address(&NATIVE_MINT, deposit_amount);
+        self.mint = NATIVE_MINT;
+        self.deposit_amount = deposit_amount;
+        let initialize = ix(
+            solana_mixer::accounts::Initialize {
+                state: self.pool,
+                tree: tree_address(&self.pool, 0),
+                treasury: treasury_address(&self.pool),
+                admin: self.admin.pubkey(),
+                system_program: system_program::ID,
+            },
+            solana_mixer::instruction::Initialize {
+                deposit_amount,
+                cluster_genesis_hash: GENESIS_HASH,
+                tree_depth,
+                root_history_size,
+            },
+        );
+        self.send_admin(initialize)?;
+        self.rotate_to_mock()
+    }
+
+    /// Token pool of `deposit_amount` base units of `mint`, on the mock verifier
+    pub fn init_token_pool(
+        &mut self,
+        mint: Pubkey,
+        token_program: Pubkey,
+        deposit_amount: u64,
+    ) -> TransactionResult {
+        self.pool = pool_address(&mint, deposit_amount);
+        self.mint = mint;
+        self.deposit_amount = deposit_amount;
+        let initialize = ix(
+            solana_mixer::accounts::InitializeTokenPool {
+                state: self.pool,
+                tree: tree_address(&self.pool, 0),
+                mint,
+                vault: vault_address(&self.pool),
+                treasury: treasury_address(&self.pool),
+                admin: self.admin.pubkey(),
+                token_program,
+                system_program: system_program::ID,
+            },
+            solana_mixer::instruction::InitializeTokenPool {
+                deposit_amount,
+                cluster_genesis_hash: GENESIS_HASH,
+                tree_depth: DEFAULT_TREE_DEPTH,
+                root_history_size: DEFAULT_ROOT_HISTORY_SIZE,
+            },
+        );
+        self.send_admin(initialize)?;
+        self.rotate_to_mock()
+    }
+
+    /// Point the pool at `MOCK_VKEY_HASH` on the mock verifier, without a grace window
+    pub fn rotate_to_mock(&mut self) -> TransactionResult {
+        self.send_admin(ix(
+            self.admin_only(),
+            solana_mixer::instruction::RotateVkey {
+                new_verifier: VerifierKind::Mock,
+                new_vkey_hash: MOCK_VKEY_HASH,
+                grace_period: 0,
+            },
+        ))
+    }
+
+    pub fn admin_only(&self) -> solana_mixer::accounts::AdminOnly {
+        solana_mixer::accounts::AdminOnly {
+            state: self.pool,
+            administrator: self.admin.pubkey(),
+        }
+    }
+
+    /// Send `instructions` paid by the first signer. The blockhash is expired
+    /// afterwards so an identical retry is not rejected as already processed.
+    pub fn send(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> TransactionResult {
+        let tx = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&signers[0].pubkey()),
+            signers,
+            self.svm.latest_blockhash(),
+        );
+        let result = self.svm.send_transaction(tx);
+        self.svm.expire_blockhash();
+        result
+    }
+
+    pub fn send_admin(&mut self, instruction: Instruction) -> TransactionResult {
+        let admin = self.admin.insecure_clone();
+        self.send(&[instruction], &[&admin])
+    }
+
+    pub fn send_user(&mut self, instruction: Instruction) -> TransactionResult {
+        let user = self.user.insecure_clone();
+        self.send(&[instruction], &[&user])
+    }
+
+    /// A new account holding `lamports`
+    pub fn funded(&mut self, lamports: u64) -> Keypair {
+        let keypair = Keypair::new();
+        self.svm.airdrop(&keypair.pubkey(), lamports).unwrap();
+        keypair
+    }
+
+    pub fn lamports(&self, address: &Pubkey) -> u64 {
+        self.svm
+            .get_account(address)
+            .map_or(0, |account| account.lamports)
+    }
+
+    pub fn state(&self) -> State {
+        let account = self.svm.get_account(&self.pool).expect("pool account");
+        State::try_deserialize(&mut &account.data[..]).unwrap()
+    }
+
+    pub fn tree_data(&self, tree_index: u32) -> Vec<u8> {
+        self.svm
+            .get_account(&tree_address(&self.pool, tree_index))
+            .expect("tree account")
+            .data
+    }
+
+    /// (current root, next leaf index) of tree `tree_index`
+    pub fn tree(&self, tree_index: u32) -> ([u8; 32], u32) {
+        let data = self.tree_data(tree_index);
+        let tree = TreeRef::new(&data).unwrap();
+        (tree.header.current_root, tree.header.next_index)
+    }
+
+    pub fn domain(&self) -> [u8; 32] {
+        domain_tag(&solana_mixer::ID, &self.pool, &GENESIS_HASH)
+    }
+
+    pub fn now(&self) -> i64 {
+        self.svm.get_sysvar::<Clock>().unix_timestamp
+    }
+
+    /// Move the clock to `unix_timestamp`
+    pub fn warp_to(&mut self, unix_timestamp: i64) {
+        let mut clock = self.svm.get_sysvar::<Clock>();
+        clock.unix_timestamp = unix_timestamp;
+        self.svm.set_sysvar(&clock);
+    }
+
+    /// Deposit `commitment` into a SOL pool from `user`
+    pub fn deposit(&mut self, commitment: [u8; 32]) -> TransactionResult {
+        let tree_index = self.state().current_tree;
+        let deposit = ix(
+            deposit_accounts(self.pool, tree_index, self.user.pubkey()),
+            solana_mixer::instruction::Deposit {
+                commitment,
+                encrypted_note: None,
+                record_bump: None,
+            },
+        );
+        self.send_user(deposit)
+    }
+
+    /// Public inputs of a full withdrawal against the latest root of the
+    /// current tree, no fee, refund or expiry
+    pub fn withdraw_inputs(
+        &self,
+        nullifier_hash: [u8; 32],
+        recipient: Pubkey,
+        relayer: Pubkey,
+    ) -> PublicInputs {
+        let tree_index = self.state().current_tree;
+        PublicInputs {
+            domain: self.domain(),
+            tree_index,
+            root: self.tree(tree_index).0,
+            nullifier_hash,
+            change_commitment: [0u8; 32],
+            recipient,
+            relayer,
+            splits_hash: [0u8; 32],
+            denomination: self.deposit_amount,
+            amount: self.deposit_amount,
+            fee: 0,
+            refund: 0,
+            expires_at: 0,
+        }
+    }
+
+    /// `withdraw` of a SOL pool proving `inputs`, submitted by `user`
+    pub fn withdraw_ix(&self, inputs: &PublicInputs) -> Instruction {
+        self.withdraw_ix_with_proof(inputs, prove(&inputs.encode()))
+    }
+
+    pub fn withdraw_ix_with_proof(&self, inputs: &PublicInputs, proof: Vec<u8>) -> Instruction {
+        let public_inputs = inputs.encode();
+        ix(
+            solana_mixer::accounts::Withdraw {
+                current_tree: Some(tree_address(&self.pool, self.state().current_tree)),
+                ..withdraw_accounts(
+                    self.pool,
+                    inputs.tree_index,
+                    self.user.pubkey(),
+                    inputs.recipient,
+                    inputs.relayer,
+                    &inputs.nullifier_hash,
+                )
+            },
+            solana_mixer::instruction::Withdraw {
+                nullifier_bytes: inputs.nullifier_hash,
+                proof,
+                public_inputs,
+                change_note: None,
+                splits: vec![],
+            },
+        )
+    }
+
+    pub fn withdraw(&mut self, inputs: &PublicInputs) -> TransactionResult {
+        let withdraw = self.withdraw_ix(inputs);
+        self.send_user(withdraw)
+    }
+}
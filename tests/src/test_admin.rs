@@ -0,0 +1,185 @@
+//! Administrator instructions in LiteSVM
+use anchor_client::solana_sdk::{pubkey::Pubkey, signer::Signer};
+use solana_mixer::{
+    verifier::mock_proof, ErrorCode, VerifierKind, DEFAULT_ROOT_HISTORY_SIZE, DEFAULT_TREE_DEPTH,
+};
+
+use crate::svm::{
+    assert_error, commitment, ix, production_program, Mixer, DEPOSIT_AMOUNT, MOCK_VKEY_HASH,
+};
+
+fn pause(mixer: &mut Mixer, halt_withdrawals: bool) {
+    let pause = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::Pause { halt_withdrawals },
+    );
+    mixer.send_admin(pause).unwrap();
+}
+
+fn unpause(mixer: &mut Mixer) {
+    let unpause = ix(mixer.admin_only(), solana_mixer::instruction::Unpause {});
+    mixer.send_admin(unpause).unwrap();
+}
+
+#[test]
+fn test_pause_stops_deposits_and_optionally_withdrawals() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+
+    // a plain pause leaves withdrawals open
+    pause(&mut mixer, false);
+    assert_error(mixer.deposit(commitment(2)), ErrorCode::DepositsPaused);
+    let inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    mixer.withdraw(&inputs).unwrap();
+
+    // the emergency stop halts both
+    unpause(&mut mixer);
+    mixer.deposit(commitment(2)).unwrap();
+    pause(&mut mixer, true);
+    assert_error(mixer.deposit(commitment(3)), ErrorCode::DepositsPaused);
+    let inputs = mixer.withdraw_inputs([2u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    assert_error(mixer.withdraw(&inputs), ErrorCode::WithdrawalsPaused);
+
+    unpause(&mut mixer);
+    mixer.deposit(commitment(3)).unwrap();
+    mixer.withdraw(&inputs).unwrap();
+    assert_eq!(mixer.state().paused, 0);
+}
+
+#[test]
+fn test_admin_transfer_needs_the_admin_and_then_the_nominee() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    let nominee = mixer.funded(DEPOSIT_AMOUNT);
+    let stranger = mixer.funded(DEPOSIT_AMOUNT);
+
+    // only the administrator may nominate, or pause
+    let propose = ix(
+        solana_mixer::accounts::AdminOnly {
+            state: mixer.pool,
+            administrator: stranger.pubkey(),
+        },
+        solana_mixer::instruction::ProposeAdmin {
+            new_admin: stranger.pubkey(),
+        },
+    );
+    assert_error(
+        mixer.send(&[propose], &[&stranger]),
+        ErrorCode::Unauthorized,
+    );
+    let stranger_pause = ix(
+        solana_mixer::accounts::AdminOnly {
+            state: mixer.pool,
+            administrator: stranger.pubkey(),
+        },
+        solana_mixer::instruction::Pause {
+            halt_withdrawals: true,
+        },
+    );
+    assert_error(
+        mixer.send(&[stranger_pause], &[&stranger]),
+        ErrorCode::Unauthorized,
+    );
+
+    let propose = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::ProposeAdmin {
+            new_admin: nominee.pubkey(),
+        },
+    );
+    mixer.send_admin(propose).unwrap();
+    assert_eq!(mixer.state().pending_administrator, nominee.pubkey());
+
+    // only the nominee may accept
+    let accept_as = |signer: &Pubkey| {
+        ix(
+            solana_mixer::accounts::AcceptAdmin {
+                state: mixer.pool,
+                pending_administrator: *signer,
+            },
+            solana_mixer::instruction::AcceptAdmin {},
+        )
+    };
+    let stranger_accept = accept_as(&stranger.pubkey());
+    let nominee_accept = accept_as(&nominee.pubkey());
+    assert_error(
+        mixer.send(&[stranger_accept], &[&stranger]),
+        ErrorCode::Unauthorized,
+    );
+    mixer.send(&[nominee_accept], &[&nominee]).unwrap();
+
+    let state = mixer.state();
+    assert_eq!(state.administrator, nominee.pubkey());
+    assert_eq!(state.pending_administrator, Pubkey::default());
+
+    // the previous administrator is out
+    let old_admin_pause = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::Pause {
+            halt_withdrawals: false,
+        },
+    );
+    assert_error(mixer.send_admin(old_admin_pause), ErrorCode::Unauthorized);
+    let nominee_pause = ix(
+        solana_mixer::accounts::AdminOnly {
+            state: mixer.pool,
+            administrator: nominee.pubkey(),
+        },
+        solana_mixer::instruction::Pause {
+            halt_withdrawals: false,
+        },
+    );
+    mixer.send(&[nominee_pause], &[&nominee]).unwrap();
+}
+
+#[test]
+fn test_retired_vkey_is_accepted_until_its_grace_window_ends() {
+    const GRACE_PERIOD: i64 = 3_600;
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    mixer.deposit(commitment(2)).unwrap();
+
+    let new_vkey_hash = [0x5e; 32];
+    let rotate = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::RotateVkey {
+            new_verifier: VerifierKind::Mock,
+            new_vkey_hash,
+            grace_period: GRACE_PERIOD,
+        },
+    );
+    mixer.send_admin(rotate).unwrap();
+    let valid_until = mixer.now() + GRACE_PERIOD;
+    let state = mixer.state();
+    assert_eq!(state.vkey_hash, new_vkey_hash);
+    assert!(state
+        .retired_vkeys
+        .iter()
+        .any(|r| r.hash == MOCK_VKEY_HASH && r.valid_until == valid_until));
+
+    // proofs for the old vkey pass up to and including `valid_until`
+    mixer.warp_to(valid_until);
+    let inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    mixer.withdraw(&inputs).unwrap();
+
+    mixer.warp_to(valid_until + 1);
+    let inputs = mixer.withdraw_inputs([2u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    assert_error(mixer.withdraw(&inputs), ErrorCode::InvalidProof);
+    let withdraw =
+        mixer.withdraw_ix_with_proof(&inputs, mock_proof(&new_vkey_hash, &inputs.encode()));
+    mixer.send_user(withdraw).unwrap();
+}
+
+#[test]
+fn test_production_build_refuses_the_mock_verifier() {
+    let mut mixer = Mixer::with_program(&production_program());
+    // initializes the pool, then tries to rotate it to the mock verifier
+    assert_error(
+        mixer.init_sol_pool(
+            DEPOSIT_AMOUNT,
+            DEFAULT_TREE_DEPTH,
+            DEFAULT_ROOT_HISTORY_SIZE,
+        ),
+        ErrorCode::VerifierUnavailable,
+    );
+    assert_eq!(mixer.state().verifier, VerifierKind::Sp1Groth16 as u8);
+}
@@ -0,0 +1,256 @@
+//! Compute units spent by the instructions that load `State`, measured in
+//! LiteSVM on the mock-verifier build (see `svm`), so a withdrawal can run
+//! without a prover. Its figure leaves out proof verification: the mock
+//! verifier is a single hash.
+//!
+//! To see what a change saves, build the program with `mock-verifier` at the
+//! commit to compare against, copy its `.so` somewhere and point
+//! `MIXER_BASELINE_SO` at it. The test runs the same transactions on both
+//! builds, prints the two columns as a markdown table and fails if any
+//! instruction got more expensive:
+//!
+//! ```text
+//! MIXER_BASELINE_SO=/tmp/baseline.so cargo test test_compute_units -- --ignored --nocapture
+//! ```
+//!
+//! The baseline is assumed to be a build from before `State` became
+//! zero-copy, whose `initialize`, `deposit` and `withdraw` take other
+//! accounts and arguments; `legacy` speaks that ABI. Set
+//! `MIXER_BASELINE_ABI=current` when the baseline already has today's ABI.
+use std::path::{Path, PathBuf};
+
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use litesvm::types::TransactionResult;
+use solana_mixer::{DEFAULT_ROOT_HISTORY_SIZE, DEFAULT_TREE_DEPTH};
+
+use crate::svm::{commitment, ix, mock_program, Mixer, DEPOSIT_AMOUNT};
+
+const DEPOSITS: u8 = 4;
+
+/// Instruction layout of the build being measured
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Abi {
+    Current,
+    /// Borsh `State`, before the zero-copy layout
+    Legacy,
+}
+
+/// Deposit a few notes into a SOL pool, withdraw one, pause and unpause,
+/// returning the compute units of every transaction
+fn measure(program: &Path, abi: Abi) -> Vec<(String, u64)> {
+    let mut mixer = Mixer::with_program(program);
+    match abi {
+        Abi::Current => mixer.init_sol_pool(
+            DEPOSIT_AMOUNT,
+            DEFAULT_TREE_DEPTH,
+            DEFAULT_ROOT_HISTORY_SIZE,
+        ),
+        Abi::Legacy => legacy::init_sol_pool(&mut mixer),
+    }
+    .unwrap();
+
+    let mut units = Vec::new();
+    let mut record = |name: String, result: TransactionResult| {
+        let meta = result.unwrap_or_else(|e| panic!("{name} failed: {:#?}", e.meta.logs));
+        units.push((name, meta.compute_units_consumed));
+    };
+    for i in 0..DEPOSITS {
+        let result = match abi {
+            Abi::Current => mixer.deposit(commitment(i)),
+            Abi::Legacy => legacy::deposit(&mut mixer, commitment(i)),
+        };
+        record(format!("deposit #{i}"), result);
+    }
+    let (recipient, relayer) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let result = match abi {
+        Abi::Current => {
+            let inputs = mixer.withdraw_inputs([1u8; 32], recipient, relayer);
+            mixer.withdraw(&inputs)
+        }
+        Abi::Legacy => legacy::withdraw(&mut mixer, [1u8; 32], recipient, relayer),
+    };
+    record("withdraw".to_string(), result);
+
+    // `pause`, `unpause` and `rotate_vkey` kept their accounts and arguments
+    let pause = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::Pause {
+            halt_withdrawals: true,
+        },
+    );
+    record("pause".to_string(), mixer.send_admin(pause));
+    let unpause = ix(mixer.admin_only(), solana_mixer::instruction::Unpause {});
+    record("unpause".to_string(), mixer.send_admin(unpause));
+    units
+}
+
+#[test]
+#[ignore = "compares against the build in MIXER_BASELINE_SO"]
+fn test_compute_units() {
+    let baseline = std::env::var_os("MIXER_BASELINE_SO")
+        .map(PathBuf::from)
+        .expect("set MIXER_BASELINE_SO to a mock-verifier build to compare against");
+    assert!(baseline.exists(), "{} not found", baseline.display());
+    let baseline_abi = match std::env::var("MIXER_BASELINE_ABI").as_deref() {
+        Ok("current") => Abi::Current,
+        Ok("legacy") | Err(_) => Abi::Legacy,
+        Ok(other) => panic!("MIXER_BASELINE_ABI is `current` or `legacy`, not `{other}`"),
+    };
+    let current = measure(&mock_program(), Abi::Current);
+    let baseline = measure(&baseline, baseline_abi);
+
+    println!("| instruction | baseline | current | saved |");
+    println!("|---|---:|---:|---:|");
+    for ((name, before), (_, after)) in baseline.iter().zip(&current) {
+        println!(
+            "| {name} | {before} | {after} | {} |",
+            *before as i64 - *after as i64
+        );
+    }
+    for ((name, before), (_, after)) in baseline.iter().zip(&current) {
+        assert!(
+            after <= before,
+            "{name} now takes {after} CU, baseline took {before}"
+        );
+    }
+}
+
+/// Instructions of the build before `State` became zero-copy, encoded by hand
+/// since today's `solana_mixer::instruction` no longer matches them: no
+/// treasury, no event CPI, no note or record arguments, and version 4 public
+/// inputs without the change commitment, splits or amounts.
+mod legacy {
+    use anchor_client::{
+        anchor_lang::solana_program::hash::hash,
+        solana_sdk::{
+            instruction::{AccountMeta, Instruction},
+            pubkey::Pubkey,
+            signer::Signer,
+            system_program,
+        },
+    };
+    use litesvm::types::TransactionResult;
+    use solana_mixer::{domain_tag, DEFAULT_ROOT_HISTORY_SIZE, DEFAULT_TREE_DEPTH, NATIVE_MINT};
+
+    use crate::svm::{prove, Mixer, DEPOSIT_AMOUNT, GENESIS_HASH};
+    use crate::utils::{nullifier_address, pool_address, tree_address};
+
+    const PUBLIC_INPUTS_VERSION: u8 = 4;
+
+    /// Anchor's `global:<name>` discriminator followed by the Borsh arguments
+    fn ix(name: &str, accounts: Vec<AccountMeta>, args: &[&[u8]]) -> Instruction {
+        let mut data = hash(format!("global:{name}").as_bytes()).to_bytes()[..8].to_vec();
+        for arg in args {
+            data.extend_from_slice(arg);
+        }
+        Instruction {
+            program_id: solana_mixer::ID,
+            accounts,
+            data,
+        }
+    }
+
+    /// Placeholder Anchor expects for an optional account that is left out
+    fn none() -> AccountMeta {
+        AccountMeta::new_readonly(solana_mixer::ID, false)
+    }
+
+    /// Borsh `Vec<u8>`: u32 length, then the bytes
+    fn bytes(value: &[u8]) -> Vec<u8> {
+        let mut out = (value.len() as u32).to_le_bytes().to_vec();
+        out.extend_from_slice(value);
+        out
+    }
+
+    pub fn init_sol_pool(mixer: &mut Mixer) -> TransactionResult {
+        mixer.pool = pool_address(&NATIVE_MINT, DEPOSIT_AMOUNT);
+        mixer.mint = NATIVE_MINT;
+        mixer.deposit_amount = DEPOSIT_AMOUNT;
+        let initialize = ix(
+            "initialize",
+            vec![
+                AccountMeta::new(mixer.pool, false),
+                AccountMeta::new(tree_address(&mixer.pool, 0), false),
+                AccountMeta::new(mixer.admin.pubkey(), true),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            &[
+                &DEPOSIT_AMOUNT.to_le_bytes(),
+                &GENESIS_HASH,
+                &[DEFAULT_TREE_DEPTH],
+                &DEFAULT_ROOT_HISTORY_SIZE.to_le_bytes(),
+            ],
+        );
+        mixer.send_admin(initialize)?;
+        mixer.rotate_to_mock()
+    }
+
+    /// Deposit into tree 0, the only one these measurements fill
+    pub fn deposit(mixer: &mut Mixer, commitment: [u8; 32]) -> TransactionResult {
+        let deposit = ix(
+            "deposit",
+            vec![
+                AccountMeta::new(mixer.pool, false),
+                AccountMeta::new(tree_address(&mixer.pool, 0), false),
+                AccountMeta::new(mixer.user.pubkey(), true),
+                none(),
+                none(),
+                none(),
+                none(),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            &[&commitment],
+        );
+        mixer.send_user(deposit)
+    }
+
+    /// Full withdrawal against the latest root of tree 0, no fee, refund or expiry
+    pub fn withdraw(
+        mixer: &mut Mixer,
+        nullifier_hash: [u8; 32],
+        recipient: Pubkey,
+        relayer: Pubkey,
+    ) -> TransactionResult {
+        let tree = tree_address(&mixer.pool, 0);
+        // the tree header then began with the pool and the current root
+        let root: [u8; 32] = mixer.svm.get_account(&tree).expect("tree account").data[40..72]
+            .try_into()
+            .unwrap();
+
+        let mut public_inputs = vec![PUBLIC_INPUTS_VERSION];
+        public_inputs.extend_from_slice(&domain_tag(&solana_mixer::ID, &mixer.pool, &GENESIS_HASH));
+        public_inputs.extend_from_slice(&0u32.to_le_bytes());
+        public_inputs.extend_from_slice(&root);
+        public_inputs.extend_from_slice(&nullifier_hash);
+        public_inputs.extend_from_slice(recipient.as_ref());
+        public_inputs.extend_from_slice(relayer.as_ref());
+        public_inputs.extend_from_slice(&0u64.to_le_bytes()); // fee
+        public_inputs.extend_from_slice(&0u64.to_le_bytes()); // refund
+        public_inputs.extend_from_slice(&0i64.to_le_bytes()); // expires_at
+
+        let withdraw = ix(
+            "withdraw",
+            vec![
+                AccountMeta::new(mixer.pool, false),
+                AccountMeta::new_readonly(tree, false),
+                AccountMeta::new(nullifier_address(&mixer.pool, &nullifier_hash), false),
+                AccountMeta::new(mixer.user.pubkey(), true),
+                AccountMeta::new(recipient, false),
+                AccountMeta::new(relayer, false),
+                none(),
+                none(),
+                none(),
+                none(),
+                none(),
+                none(),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            &[
+                &nullifier_hash,
+                &bytes(&prove(&public_inputs)),
+                &bytes(&public_inputs),
+            ],
+        );
+        mixer.send_user(withdraw)
+    }
+}
@@ -0,0 +1,155 @@
+//! `deposit` and `deposit_batch` in LiteSVM
+use anchor_client::solana_sdk::{instruction::AccountMeta, pubkey::Pubkey, signer::Signer};
+use litesvm::types::TransactionResult;
+use solana_mixer::{ErrorCode, MAX_DEPOSIT_BATCH, ZERO_HASHES};
+
+use crate::{
+    svm::{assert_error, commitment, ix, Mixer, DEPOSIT_AMOUNT},
+    utils::{commitment_record, deposit_accounts},
+};
+
+/// BN254 scalar field modulus r, little endian
+const SCALAR_MODULUS: [u8; 32] = [
+    1, 0, 0, 240, 147, 245, 225, 67, 145, 112, 185, 121, 72, 232, 51, 40, 93, 88, 129, 129, 182,
+    69, 80, 184, 41, 160, 49, 225, 114, 78, 100, 48,
+];
+
+/// `deposit_batch` from `user`, passing a commitment record per commitment
+/// when the pool has `unique_commitments` on
+fn deposit_batch(mixer: &mut Mixer, commitments: Vec<[u8; 32]>) -> TransactionResult {
+    let records: Vec<_> = if mixer.state().unique_commitments != 0 {
+        commitments
+            .iter()
+            .map(|c| commitment_record(&mixer.pool, c))
+            .collect()
+    } else {
+        vec![]
+    };
+    deposit_with_records(mixer, commitments, &records)
+}
+
+/// `deposit_batch` from `user` with the given `(record, bump)` pairs
+fn deposit_with_records(
+    mixer: &mut Mixer,
+    commitments: Vec<[u8; 32]>,
+    records: &[(Pubkey, u8)],
+) -> TransactionResult {
+    let mut deposit = ix(
+        deposit_accounts(mixer.pool, mixer.state().current_tree, mixer.user.pubkey()),
+        solana_mixer::instruction::DepositBatch {
+            commitments,
+            encrypted_notes: None,
+            record_bumps: (!records.is_empty())
+                .then(|| records.iter().map(|(_, bump)| *bump).collect()),
+        },
+    );
+    deposit.accounts.extend(
+        records
+            .iter()
+            .map(|(record, _)| AccountMeta::new(*record, false)),
+    );
+    mixer.send_user(deposit)
+}
+
+fn set_unique_commitments(mixer: &mut Mixer, enabled: bool) {
+    let set = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::SetUniqueCommitments { enabled },
+    );
+    mixer.send_admin(set).unwrap();
+}
+
+#[test]
+fn test_deposit_batch_size_is_bounded() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    assert_error(
+        deposit_batch(&mut mixer, vec![]),
+        ErrorCode::InvalidBatchSize,
+    );
+    let oversized = (0..=MAX_DEPOSIT_BATCH as u8).map(commitment).collect();
+    assert_error(
+        deposit_batch(&mut mixer, oversized),
+        ErrorCode::InvalidBatchSize,
+    );
+    assert_eq!(mixer.tree(0).1, 0);
+
+    let pool_before = mixer.lamports(&mixer.pool);
+    let full = (0..MAX_DEPOSIT_BATCH as u8).map(commitment).collect();
+    deposit_batch(&mut mixer, full).unwrap();
+    assert_eq!(mixer.tree(0).1, MAX_DEPOSIT_BATCH as u32);
+    assert_eq!(
+        mixer.lamports(&mixer.pool),
+        pool_before + MAX_DEPOSIT_BATCH as u64 * DEPOSIT_AMOUNT
+    );
+}
+
+#[test]
+fn test_commitment_must_be_a_field_element() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    assert_error(
+        mixer.deposit(SCALAR_MODULUS),
+        ErrorCode::CommitmentNotInField,
+    );
+    assert_error(mixer.deposit([0xff; 32]), ErrorCode::CommitmentNotInField);
+
+    // r - 1 is the largest one
+    let mut largest = SCALAR_MODULUS;
+    largest[0] -= 1;
+    mixer.deposit(largest).unwrap();
+    assert_eq!(mixer.tree(0).1, 1);
+}
+
+#[test]
+fn test_empty_leaf_values_are_reserved() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    assert_error(mixer.deposit([0u8; 32]), ErrorCode::ReservedCommitment);
+    assert_error(mixer.deposit(ZERO_HASHES[0]), ErrorCode::ReservedCommitment);
+    // one bad commitment fails the whole batch
+    assert_error(
+        deposit_batch(&mut mixer, vec![commitment(1), ZERO_HASHES[0]]),
+        ErrorCode::ReservedCommitment,
+    );
+    assert_eq!(mixer.tree(0).1, 0);
+}
+
+#[test]
+fn test_unique_commitments_reject_repeats() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    set_unique_commitments(&mut mixer, true);
+
+    // within one batch
+    assert_error(
+        deposit_batch(&mut mixer, vec![commitment(1), commitment(1)]),
+        ErrorCode::DuplicateCommitment,
+    );
+    deposit_batch(&mut mixer, vec![commitment(1), commitment(2)]).unwrap();
+    assert!(mixer
+        .svm
+        .get_account(&commitment_record(&mixer.pool, &commitment(2)).0)
+        .is_some_and(|record| record.owner == solana_mixer::ID));
+
+    // across deposits
+    assert_error(
+        deposit_batch(&mut mixer, vec![commitment(2)]),
+        ErrorCode::DuplicateCommitment,
+    );
+    // a deposit without its records, or with a bump that does not derive them
+    assert_error(
+        mixer.deposit(commitment(3)),
+        ErrorCode::InvalidCommitmentRecord,
+    );
+    let (record, bump) = commitment_record(&mixer.pool, &commitment(3));
+    assert_error(
+        deposit_with_records(&mut mixer, vec![commitment(3)], &[(record, bump ^ 1)]),
+        ErrorCode::InvalidCommitmentRecord,
+    );
+    assert_error(
+        deposit_with_records(&mut mixer, vec![commitment(3)], &[]),
+        ErrorCode::InvalidCommitmentRecord,
+    );
+    assert_eq!(mixer.tree(0).1, 2);
+
+    // repeats are only tracked while enabled
+    set_unique_commitments(&mut mixer, false);
+    mixer.deposit(commitment(1)).unwrap();
+}
@@ -0,0 +1,169 @@
+//! The on-chain tree code (`merkle_tree`) run natively on plain buffers
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use solana_mixer::{
+    merkle_tree::{check_tree_config, MerkleTree, TreeMut, TreeRef},
+    ErrorCode, MAX_ROOT_HISTORY_SIZE, MAX_TREE_DEPTH,
+};
+
+use crate::merkle::{PoseidonHash, ZERO_HASHES};
+
+/// Zeroed backing store for a tree account; `u64` words keep the header aligned
+struct TreeAccount {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl TreeAccount {
+    fn new(depth: u8, root_history_size: u16) -> Self {
+        let len = MerkleTree::space(depth, root_history_size);
+        let mut account = TreeAccount {
+            words: vec![0; len.div_ceil(8)],
+            len,
+        };
+        TreeMut::init(
+            account.data_mut(),
+            Pubkey::new_unique(),
+            0,
+            depth,
+            root_history_size,
+        );
+        account
+    }
+
+    fn data(&self) -> &[u8] {
+        &bytemuck::cast_slice(&self.words)[..self.len]
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        &mut bytemuck::cast_slice_mut(&mut self.words)[..self.len]
+    }
+
+    fn tree(&mut self) -> TreeMut<'_> {
+        TreeMut::new(self.data_mut())
+    }
+
+    fn view(&self) -> TreeRef<'_> {
+        TreeRef::new(self.data()).unwrap()
+    }
+}
+
+/// Leaf `i`, a small field element
+fn leaf(i: u32) -> [u8; 32] {
+    let mut leaf = [0u8; 32];
+    leaf[..4].copy_from_slice(&(i + 1).to_le_bytes());
+    leaf
+}
+
+/// Fill `start` leaves one at a time, then add `count` more with a single
+/// `insert_batch` on one tree and one `insert` each on another; both must end
+/// up the same
+fn assert_batch_matches_inserts(depth: u8, start: u32, count: u32) {
+    let mut batched = TreeAccount::new(depth, 8);
+    let mut single = TreeAccount::new(depth, 8);
+    for i in 0..start {
+        batched.tree().insert(leaf(i)).unwrap();
+        single.tree().insert(leaf(i)).unwrap();
+    }
+
+    let leaves: Vec<_> = (start..start + count).map(leaf).collect();
+    assert_eq!(batched.tree().insert_batch(&leaves).unwrap(), start);
+    for (i, leaf) in leaves.iter().enumerate() {
+        assert_eq!(single.tree().insert(*leaf).unwrap(), start + i as u32);
+    }
+
+    let (batched, single) = (batched.view(), single.view());
+    let case = format!("depth {depth}, {count} leaves from {start}");
+    assert_eq!(batched.header.next_index, start + count, "{case}");
+    assert_eq!(
+        batched.header.current_root, single.header.current_root,
+        "{case}"
+    );
+    assert_eq!(batched.filled_subtrees, single.filled_subtrees, "{case}");
+    assert!(batched.is_known_root(&single.header.current_root), "{case}");
+}
+
+#[test]
+fn test_insert_batch_matches_repeated_insert() {
+    // a batch of 1 at either side of a pair
+    assert_batch_matches_inserts(4, 0, 1);
+    assert_batch_matches_inserts(4, 1, 1);
+    // odd start, the first leaf pairs with a stored sibling
+    assert_batch_matches_inserts(4, 3, 2);
+    assert_batch_matches_inserts(4, 5, 6);
+    // crossing the boundary between the two subtrees of 8
+    assert_batch_matches_inserts(4, 6, 4);
+    assert_batch_matches_inserts(4, 7, 9);
+    // the whole tree at once, and up to the last leaf
+    assert_batch_matches_inserts(4, 0, 16);
+    assert_batch_matches_inserts(4, 12, 4);
+
+    // and every batch that fits a tree of 8
+    for start in 0..8 {
+        for count in 1..=8 - start {
+            assert_batch_matches_inserts(3, start, count);
+        }
+    }
+}
+
+#[test]
+fn test_insert_batch_past_the_last_leaf() {
+    let mut account = TreeAccount::new(2, 8);
+    let mut tree = account.tree();
+    tree.insert_batch(&[leaf(0), leaf(1), leaf(2)]).unwrap();
+    let root = tree.header.current_root;
+
+    // nothing is written when the batch does not fit
+    let full = Err(ErrorCode::TreeFull.into());
+    assert_eq!(tree.insert_batch(&[leaf(3), leaf(4)]), full);
+    assert_eq!(tree.header.next_index, 3);
+    assert_eq!(tree.header.current_root, root);
+
+    assert_eq!(tree.insert_batch(&[leaf(3)]), Ok(3));
+    assert_eq!(tree.insert(leaf(4)), full);
+    assert_eq!(tree.insert_batch(&[]), Err(ErrorCode::InvalidInput.into()));
+}
+
+#[test]
+fn test_check_tree_config_bounds() {
+    let invalid = Err(ErrorCode::InvalidTreeConfig.into());
+    assert_eq!(check_tree_config(0, 32), invalid);
+    assert_eq!(check_tree_config(MAX_TREE_DEPTH + 1, 32), invalid);
+    assert_eq!(check_tree_config(20, 0), invalid);
+    assert_eq!(check_tree_config(20, MAX_ROOT_HISTORY_SIZE + 1), invalid);
+
+    assert_eq!(MAX_TREE_DEPTH, 30);
+    assert_eq!(MAX_ROOT_HISTORY_SIZE, 256);
+    check_tree_config(1, 1).unwrap();
+    check_tree_config(MAX_TREE_DEPTH, MAX_ROOT_HISTORY_SIZE).unwrap();
+}
+
+#[test]
+fn test_zero_hashes_match_the_off_chain_tree() {
+    assert_eq!(solana_mixer::ZERO_HASHES, ZERO_HASHES);
+    for level in 1..ZERO_HASHES.len() {
+        let below = &ZERO_HASHES[level - 1];
+        assert_eq!(PoseidonHash::hash_pair(below, below).0, ZERO_HASHES[level]);
+    }
+
+    // small trees copy a prefix of the table
+    for depth in [1, 2, 5] {
+        let account = TreeAccount::new(depth, 4);
+        let tree = account.view();
+        assert_eq!(tree.zeros, &ZERO_HASHES[..depth as usize]);
+        assert_eq!(tree.filled_subtrees, tree.zeros);
+        assert_eq!(tree.header.current_root, ZERO_HASHES[depth as usize - 1]);
+        assert!(tree
+            .root_history
+            .iter()
+            .all(|r| *r == tree.header.current_root));
+    }
+
+    // deeper ones hash the levels past it
+    let account = TreeAccount::new(MAX_TREE_DEPTH, 4);
+    let zeros = account.view().zeros;
+    assert_eq!(zeros[..ZERO_HASHES.len()], ZERO_HASHES);
+    for level in ZERO_HASHES.len()..zeros.len() {
+        let below = &zeros[level - 1];
+        assert_eq!(PoseidonHash::hash_pair(below, below).0, zeros[level]);
+    }
+}
@@ -15,17 +15,24 @@ use anchor_client::{
 };
 
 use crate::{
+    note::{encrypt_note, scan_notes, Note, ViewingKey},
     off_proof::{
         compute_exact_onchain_root, compute_root, merkle_check, merkle_check_circom, merkle_path,
     },
-    utils::{biguint_to_32_le_bytes, fetch_deposits},
+    utils::{
+        biguint_to_32_le_bytes, deposit_accounts, fetch_deposits, pool_address, treasury_address,
+        tree_address, withdraw_accounts,
+    },
 };
 
 use mixer_lib::utils::to_hex32;
 use num_bigint::BigUint;
 use reqwest::Client as ClientRequest;
 use serde::{Deserialize, Serialize};
-use solana_mixer::{id as mixer_program_id, State};
+use solana_mixer::{
+    domain_tag, id as mixer_program_id, merkle_tree::TreeRef, PublicInputs, State,
+    DEFAULT_ROOT_HISTORY_SIZE, DEFAULT_TREE_DEPTH, NATIVE_MINT,
+};
 
 use tokio;
 use tokio::runtime::Runtime;
@@ -45,7 +52,8 @@ struct PublicInputsWrapper {
 struct BufferData {
     data: Vec<u8>,
 }
-pub const STATE_SEED: &[u8] = b"mixer_state";
+/// Denomination of the pool exercised by these tests (1 SOL)
+const DEPOSIT_AMOUNT: u64 = 1_000_000_000;
 
 #[test]
 
@@ -67,18 +75,25 @@ fn test_initialize_and_deposit() {
         .unwrap();
 
     let program_id = mixer_program_id();
-    let (state_pubkey, _state_bump) = Pubkey::find_program_address(&[STATE_SEED], &program_id);
+    let state_pubkey = pool_address(&NATIVE_MINT, DEPOSIT_AMOUNT);
+    let genesis_hash = program.rpc().get_genesis_hash().unwrap().to_bytes();
+    let domain = domain_tag(&program_id, &state_pubkey, &genesis_hash);
 
     eprintln!("test_initialize_and_deposit 3");
     let sig_init = program
         .request()
         .accounts(solana_mixer::accounts::Initialize {
             state: state_pubkey,
+            tree: tree_address(&state_pubkey, 0),
+            treasury: treasury_address(&state_pubkey),
             admin: payer.pubkey(),
             system_program: system_program::ID,
         })
         .args(solana_mixer::instruction::Initialize {
-            deposit_amount: 1_000_000_000,
+            deposit_amount: DEPOSIT_AMOUNT,
+            cluster_genesis_hash: genesis_hash,
+            tree_depth: DEFAULT_TREE_DEPTH,
+            root_history_size: DEFAULT_ROOT_HISTORY_SIZE,
         })
         .signer(&payer)
         .send()
@@ -99,16 +114,20 @@ fn test_initialize_and_deposit() {
     let state_for_key: State = program.account::<State>(state_pubkey).unwrap();
     print!("State {:?}", state_for_key);
 
-    // deposit of an actual commitment
+    // deposit of an actual commitment, with the note encrypted to our viewing key
+    let viewing_key = ViewingKey::generate();
+    let note = Note {
+        nullifier: biguint_to_32_le_bytes(&nullifier),
+        secret: biguint_to_32_le_bytes(&secret),
+        value: DEPOSIT_AMOUNT,
+    };
     let sig_deposit = program
         .request()
-        .accounts(solana_mixer::accounts::Deposit {
-            state: state_pubkey,
-            depositor: payer.pubkey(),
-            system_program: system_program::ID,
-        })
+        .accounts(deposit_accounts(state_pubkey, 0, payer.pubkey()))
         .args(solana_mixer::instruction::Deposit {
             commitment: commitment,
+            encrypted_note: Some(encrypt_note(&viewing_key.public(), &commitment, &note)),
+            record_bump: None,
         })
         .signer(&payer)
         .send()
@@ -116,6 +135,11 @@ fn test_initialize_and_deposit() {
 
     eprintln!("deposit sig: {}", sig_deposit);
 
+    let recovered = scan_notes(state_pubkey, &viewing_key).unwrap();
+    assert_eq!(recovered.len(), 1);
+    assert_eq!(recovered[0].note, note);
+    assert_eq!(recovered[0].commitment, commitment);
+
     let acc2_state = program.account::<State>(state_pubkey).unwrap();
     print!("State {:?}", acc2_state);
 
@@ -126,13 +150,11 @@ fn test_initialize_and_deposit() {
     // deposit of an actual commitment
     let sig_deposit = program
         .request()
-        .accounts(solana_mixer::accounts::Deposit {
-            state: state_pubkey,
-            depositor: payer.pubkey(),
-            system_program: system_program::ID,
-        })
+        .accounts(deposit_accounts(state_pubkey, 0, payer.pubkey()))
         .args(solana_mixer::instruction::Deposit {
             commitment: commitment1,
+            encrypted_note: None,
+            record_bump: None,
         })
         .signer(&payer)
         .send()
@@ -147,13 +169,11 @@ fn test_initialize_and_deposit() {
         // deposit of an actual commitment
         let sig_deposit = program
             .request()
-            .accounts(solana_mixer::accounts::Deposit {
-                state: state_pubkey,
-                depositor: payer.pubkey(),
-                system_program: system_program::ID,
-            })
+            .accounts(deposit_accounts(state_pubkey, 0, payer.pubkey()))
             .args(solana_mixer::instruction::Deposit {
                 commitment: commitment1,
+                encrypted_note: None,
+                record_bump: None,
             })
             .signer(&payer)
             .send()
@@ -167,13 +187,11 @@ fn test_initialize_and_deposit() {
         // deposit of an actual commitment
         let sig_deposit = program
             .request()
-            .accounts(solana_mixer::accounts::Deposit {
-                state: state_pubkey,
-                depositor: payer.pubkey(),
-                system_program: system_program::ID,
-            })
+            .accounts(deposit_accounts(state_pubkey, 0, payer.pubkey()))
             .args(solana_mixer::instruction::Deposit {
                 commitment: commitment3,
+                encrypted_note: None,
+                record_bump: None,
             })
             .signer(&payer)
             .send()
@@ -189,13 +207,11 @@ fn test_initialize_and_deposit() {
         // deposit of an actual commitment
         let sig_deposit = program
             .request()
-            .accounts(solana_mixer::accounts::Deposit {
-                state: state_pubkey,
-                depositor: payer.pubkey(),
-                system_program: system_program::ID,
-            })
+            .accounts(deposit_accounts(state_pubkey, 0, payer.pubkey()))
             .args(solana_mixer::instruction::Deposit {
                 commitment: commitment2,
+                encrypted_note: None,
+                record_bump: None,
             })
             .signer(&payer)
             .send()
@@ -213,7 +229,7 @@ fn test_initialize_and_deposit() {
         deposit_leaf_indices,
         commitment_leaf_index,
         my_commitment_found,
-    ) = fetch_deposits(commitment1).unwrap();
+    ) = fetch_deposits(state_pubkey, 0, commitment1).unwrap();
 
     let (path_elems_for_proof, path_inds_for_proof, root_for_proof): (
         [[u8; 32]; 20],
@@ -247,10 +263,14 @@ fn test_initialize_and_deposit() {
     let mut path_elems_for_proof: [[u8; 32]; 20] = siblings.try_into().unwrap();
     let mut path_inds_for_proof: [u8; 20] = path_indices.try_into().unwrap();
     let mut root_for_proof = current_root;
-    let _withdraw_state: State = program.account::<State>(state_pubkey).unwrap();
+    let tree_data = program
+        .rpc()
+        .get_account_data(&tree_address(&state_pubkey, 0))
+        .unwrap();
+    let _withdraw_state = TreeRef::new(&tree_data).unwrap();
     eprintln!(
         "\n current root {:?} \n root_for_proof {:?}",
-        _withdraw_state.current_root, root_for_proof
+        _withdraw_state.header.current_root, root_for_proof
     );
 
     let siblings_array: [[u8; 32]; 20] = path_elems_for_proof.try_into().unwrap();
@@ -302,7 +322,11 @@ fn test_initialize_and_deposit() {
         return;
     }
 
-    let _withdraw_state: State = program.account::<State>(state_pubkey).unwrap();
+    let tree_data = program
+        .rpc()
+        .get_account_data(&tree_address(&state_pubkey, 0))
+        .unwrap();
+    let _withdraw_state = TreeRef::new(&tree_data).unwrap();
 
     let mut found = false;
     for &r in _withdraw_state.root_history.iter() {
@@ -360,8 +384,10 @@ fn test_initialize_and_deposit() {
     let nullifier_hash: [u8; 32] = nullifier_hash;
     let recipient: [u8; 32] = new_withdrawal_recipient_address.pubkey().to_bytes();
     let relayer: [u8; 32] = new_relayer_address.pubkey().to_bytes();
+    let amount = DEPOSIT_AMOUNT;
     let fee = 0;
     let refund = 0;
+    let expires_at = 0;
     let nullifier: BigUint = nullifier;
     let secret: BigUint = secret;
     let path_elems: Vec<[u8; 32]> = path_elems_for_proof.to_vec();
@@ -379,12 +405,16 @@ fn test_initialize_and_deposit() {
     );
 
     let req = build_prove_request(
+        domain,
+        0,
         root,
         nullifier_hash,
         recipient,
         relayer,
+        amount,
         fee,
         refund,
+        expires_at,
         nullifier,
         secret,
         path_elems,
@@ -400,37 +430,37 @@ fn test_initialize_and_deposit() {
     let proof_bytes: Vec<u8> = hex::decode(&resp.proof).unwrap();
 
     let public_inputs: Vec<u8> = resp.public_inputs.buffer.data;
+    let decoded_inputs = PublicInputs::decode(&public_inputs).unwrap();
 
     let compute_increase: Instruction = ComputeBudgetInstruction::set_compute_unit_limit(500500);
 
     let (root_account_withdraw_pubkey, _) =
-        Pubkey::find_program_address(&[b"root", &public_inputs[0..32]], &program_id);
-
-    let (nullifier_account_withdraw_pubkey, _) =
-        Pubkey::find_program_address(&[&public_inputs[32..64]], &program_id);
+        Pubkey::find_program_address(&[b"root", &decoded_inputs.root], &program_id);
 
     eprint!("\n{:?}\n", root_account_withdraw_pubkey);
     eprintln!(
         "\nLE commitment bytes {:?} \n LE u32 commitment bytes {:?}",
-        &public_inputs[0..32],
+        &decoded_inputs.root,
         commitment_leaf_index.to_le_bytes()
     );
 
     let sig_withdraw = program
         .request()
         .instruction(compute_increase)
-        .accounts(solana_mixer::accounts::Withdraw {
-            state: state_pubkey,
-            caller: payer.pubkey(),
-            recipient: new_withdrawal_recipient_address.pubkey(),
-            nullifier: nullifier_account_withdraw_pubkey,
-            relayer: new_relayer_address.pubkey(),
-            system_program: system_program::ID,
-        })
+        .accounts(withdraw_accounts(
+            state_pubkey,
+            decoded_inputs.tree_index,
+            payer.pubkey(),
+            new_withdrawal_recipient_address.pubkey(),
+            new_relayer_address.pubkey(),
+            &decoded_inputs.nullifier_hash,
+        ))
         .args(solana_mixer::instruction::Withdraw {
             nullifier_bytes: nullifier_hash,
             proof: proof_bytes,
             public_inputs: public_inputs,
+            change_note: None,
+            splits: vec![],
         })
         .signer(&payer)
         .send()
@@ -466,13 +496,11 @@ fn test_deposit(program: &Program<&Keypair>, state_pubkey: Pubkey, payer: &Keypa
     let sig_deposit = program
         .request()
         .instruction(compute_increase)
-        .accounts(solana_mixer::accounts::Deposit {
-            state: state_pubkey,
-            depositor: payer.pubkey(),
-            system_program: system_program::ID,
-        })
+        .accounts(deposit_accounts(state_pubkey, 0, payer.pubkey()))
         .args(solana_mixer::instruction::Deposit {
             commitment: commitment,
+            encrypted_note: None,
+            record_bump: None,
         })
         .signer(&payer)
         .send()
@@ -489,7 +517,7 @@ fn test_deposit(program: &Program<&Keypair>, state_pubkey: Pubkey, payer: &Keypa
         deposit_leaf_indices,
         commitment_leaf_index,
         my_commitment_found,
-    ) = fetch_deposits(commitment).unwrap();
+    ) = fetch_deposits(state_pubkey, 0, commitment).unwrap();
 
     let (path_elems_for_proof, path_inds_for_proof, root_for_proof): (
         [[u8; 32]; 20],
@@ -522,7 +550,11 @@ fn test_deposit(program: &Program<&Keypair>, state_pubkey: Pubkey, payer: &Keypa
         return;
     }
 
-    let _withdraw_state: State = program.account::<State>(state_pubkey).unwrap();
+    let tree_data = program
+        .rpc()
+        .get_account_data(&tree_address(&state_pubkey, 0))
+        .unwrap();
+    let _withdraw_state = TreeRef::new(&tree_data).unwrap();
 
     let mut found = false;
     for &r in _withdraw_state.root_history.iter() {
@@ -560,7 +592,9 @@ fn test_deposit_withdrawal() {
         .unwrap();
 
     let program_id = mixer_program_id();
-    let (state_pubkey, _state_bump) = Pubkey::find_program_address(&[STATE_SEED], &program_id);
+    let state_pubkey = pool_address(&NATIVE_MINT, DEPOSIT_AMOUNT);
+    let genesis_hash = program.rpc().get_genesis_hash().unwrap().to_bytes();
+    let domain = domain_tag(&program_id, &state_pubkey, &genesis_hash);
 
     eprintln!("\n test_initialize_and_deposit 4, Assert matches");
 
@@ -573,13 +607,11 @@ fn test_deposit_withdrawal() {
 
     let sig_deposit = program
         .request()
-        .accounts(solana_mixer::accounts::Deposit {
-            state: state_pubkey,
-            depositor: payer.pubkey(),
-            system_program: system_program::ID,
-        })
+        .accounts(deposit_accounts(state_pubkey, 0, payer.pubkey()))
         .args(solana_mixer::instruction::Deposit {
             commitment: commitment,
+            encrypted_note: None,
+            record_bump: None,
         })
         .signer(&payer)
         .send()
@@ -596,7 +628,7 @@ fn test_deposit_withdrawal() {
         deposit_leaf_indices,
         commitment_leaf_index,
         my_commitment_found,
-    ) = fetch_deposits(commitment).unwrap();
+    ) = fetch_deposits(state_pubkey, 0, commitment).unwrap();
 
     let (path_elems_for_proof, path_inds_for_proof, root_for_proof): (
         [[u8; 32]; 20],
@@ -628,7 +660,11 @@ fn test_deposit_withdrawal() {
         eprintln!("my_commitment_not_found");
         return;
     }
-    let _withdraw_state: State = program.account::<State>(state_pubkey).unwrap();
+    let tree_data = program
+        .rpc()
+        .get_account_data(&tree_address(&state_pubkey, 0))
+        .unwrap();
+    let _withdraw_state = TreeRef::new(&tree_data).unwrap();
 
     let mut found = false;
     for &r in _withdraw_state.root_history.iter() {
@@ -677,8 +713,10 @@ fn test_deposit_withdrawal() {
     let nullifier_hash: [u8; 32] = nullifier_hash;
     let recipient: [u8; 32] = new_withdrawal_recipient_address.pubkey().to_bytes();
     let relayer: [u8; 32] = new_relayer_address.pubkey().to_bytes();
+    let amount = DEPOSIT_AMOUNT;
     let fee = 0;
     let refund = 0;
+    let expires_at = 0;
     let nullifier: BigUint = nullifier;
     let secret: BigUint = secret;
     let path_elems: Vec<[u8; 32]> = path_elems_for_proof.to_vec();
@@ -691,12 +729,16 @@ fn test_deposit_withdrawal() {
     );
 
     let req = build_prove_request(
+        domain,
+        0,
         root,
         nullifier_hash,
         recipient,
         relayer,
+        amount,
         fee,
         refund,
+        expires_at,
         nullifier,
         secret,
         path_elems,
@@ -716,36 +758,36 @@ fn test_deposit_withdrawal() {
     let proof_bytes: Vec<u8> = hex::decode(&resp.proof).unwrap();
 
     let public_inputs: Vec<u8> = resp.public_inputs.buffer.data;
+    let decoded_inputs = PublicInputs::decode(&public_inputs).unwrap();
 
     let compute_increase: Instruction = ComputeBudgetInstruction::set_compute_unit_limit(500500);
 
     let (root_account_withdraw_pubkey, _) =
-        Pubkey::find_program_address(&[b"root", &(public_inputs[0..32])], &program_id);
+        Pubkey::find_program_address(&[b"root", &decoded_inputs.root], &program_id);
 
     eprint!("\n{:?}\n", root_account_withdraw_pubkey);
     eprintln!(
         "\nLE commitment bytes {:?} \n LE u32 commitment bytes {:?}",
-        &public_inputs[0..32],
+        &decoded_inputs.root,
         commitment_leaf_index.to_le_bytes()
     );
-    let (nullifier_account_withdraw_pubkey, _) =
-        Pubkey::find_program_address(&[&public_inputs[32..64]], &program_id);
-
     let sig_withdraw = program
         .request()
         .instruction(compute_increase)
-        .accounts(solana_mixer::accounts::Withdraw {
-            state: state_pubkey,
-            caller: payer.pubkey(),
-            recipient: new_withdrawal_recipient_address.pubkey(),
-            nullifier: nullifier_account_withdraw_pubkey,
-            relayer: new_relayer_address.pubkey(),
-            system_program: system_program::ID,
-        })
+        .accounts(withdraw_accounts(
+            state_pubkey,
+            decoded_inputs.tree_index,
+            payer.pubkey(),
+            new_withdrawal_recipient_address.pubkey(),
+            new_relayer_address.pubkey(),
+            &decoded_inputs.nullifier_hash,
+        ))
         .args(solana_mixer::instruction::Withdraw {
             nullifier_bytes: nullifier_hash,
             proof: proof_bytes,
             public_inputs: public_inputs,
+            change_note: None,
+            splits: vec![],
         })
         .signer(&payer)
         .send()
@@ -776,7 +818,9 @@ fn test_deposit_withdrawal_again() {
         .unwrap();
 
     let program_id = mixer_program_id();
-    let (state_pubkey, _state_bump) = Pubkey::find_program_address(&[STATE_SEED], &program_id);
+    let state_pubkey = pool_address(&NATIVE_MINT, DEPOSIT_AMOUNT);
+    let genesis_hash = program.rpc().get_genesis_hash().unwrap().to_bytes();
+    let domain = domain_tag(&program_id, &state_pubkey, &genesis_hash);
 
     eprintln!("\n test_initialize_and_deposit 4, Assert matches");
 
@@ -788,14 +832,11 @@ fn test_deposit_withdrawal_again() {
 
     let sig_deposit = program
         .request()
-        .accounts(solana_mixer::accounts::Deposit {
-            state: state_pubkey,
-
-            depositor: payer.pubkey(),
-            system_program: system_program::ID,
-        })
+        .accounts(deposit_accounts(state_pubkey, 0, payer.pubkey()))
         .args(solana_mixer::instruction::Deposit {
             commitment: commitment,
+            encrypted_note: None,
+            record_bump: None,
         })
         .signer(&payer)
         .send()
@@ -812,7 +853,7 @@ fn test_deposit_withdrawal_again() {
         deposit_leaf_indices,
         commitment_leaf_index,
         my_commitment_found,
-    ) = fetch_deposits(commitment).unwrap();
+    ) = fetch_deposits(state_pubkey, 0, commitment).unwrap();
 
     let (path_elems_for_proof, path_inds_for_proof, root_for_proof): (
         [[u8; 32]; 20],
@@ -846,7 +887,11 @@ fn test_deposit_withdrawal_again() {
         return;
     }
 
-    let _withdraw_state: State = program.account::<State>(state_pubkey).unwrap();
+    let tree_data = program
+        .rpc()
+        .get_account_data(&tree_address(&state_pubkey, 0))
+        .unwrap();
+    let _withdraw_state = TreeRef::new(&tree_data).unwrap();
 
     let mut found = false;
     for &r in _withdraw_state.root_history.iter() {
@@ -875,20 +920,26 @@ fn test_deposit_withdrawal_again() {
     let nullifier_hash: [u8; 32] = nullifier_hash;
     let recipient: [u8; 32] = new_withdrawal_recipient_address.pubkey().to_bytes();
     let relayer: [u8; 32] = new_relayer_address.pubkey().to_bytes();
+    let amount = DEPOSIT_AMOUNT;
     let fee = 0;
     let refund = 0;
+    let expires_at = 0;
     let nullifier: BigUint = nullifier;
     let secret: BigUint = secret;
     let path_elems: Vec<[u8; 32]> = path_elems_for_proof.to_vec();
     let path_inds: Vec<u8> = path_inds_for_proof.to_vec();
 
     let req = build_prove_request(
+        domain,
+        0,
         root,
         nullifier_hash,
         recipient,
         relayer,
+        amount,
         fee,
         refund,
+        expires_at,
         nullifier,
         secret,
         path_elems,
@@ -904,19 +955,17 @@ fn test_deposit_withdrawal_again() {
     let proof_bytes: Vec<u8> = hex::decode(&resp.proof).unwrap();
 
     let public_inputs: Vec<u8> = resp.public_inputs.buffer.data;
+    let decoded_inputs = PublicInputs::decode(&public_inputs).unwrap();
 
     let compute_increase: Instruction = ComputeBudgetInstruction::set_compute_unit_limit(500500);
 
     let (root_account_withdraw_pubkey, _) =
-        Pubkey::find_program_address(&[b"root", &(public_inputs[0..32])], &program_id);
-
-    let (nullifier_account_withdraw_pubkey, _) =
-        Pubkey::find_program_address(&[&public_inputs[32..64]], &program_id);
+        Pubkey::find_program_address(&[b"root", &decoded_inputs.root], &program_id);
 
     eprint!("\n{:?}\n", root_account_withdraw_pubkey);
     eprintln!(
         "\nLE commitment bytes {:?} \n LE u32 commitment bytes {:?}",
-        &public_inputs[0..32],
+        &decoded_inputs.root,
         commitment_leaf_index.to_le_bytes()
     );
 
@@ -928,18 +977,20 @@ fn test_deposit_withdrawal_again() {
     let sig_withdraw = program
         .request()
         .instruction(compute_increase)
-        .accounts(solana_mixer::accounts::Withdraw {
-            state: state_pubkey,
-            caller: payer.pubkey(),
-            recipient: new_withdrawal_recipient_address.pubkey(),
-            nullifier: nullifier_account_withdraw_pubkey,
-            relayer: new_relayer_address.pubkey(),
-            system_program: system_program::ID,
-        })
+        .accounts(withdraw_accounts(
+            state_pubkey,
+            decoded_inputs.tree_index,
+            payer.pubkey(),
+            new_withdrawal_recipient_address.pubkey(),
+            new_relayer_address.pubkey(),
+            &decoded_inputs.nullifier_hash,
+        ))
         .args(solana_mixer::instruction::Withdraw {
             nullifier_bytes: nullifier_hash,
             proof: proof_bytes,
             public_inputs: public_inputs,
+            change_note: None,
+            splits: vec![],
         })
         .signer(&payer)
         .send()
@@ -957,12 +1008,19 @@ fn test_deposit_withdrawal_again() {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ProveRequest {
+    pub domain: String,
+    pub tree_index: u32,
     pub root: String,
     pub nullifier_hash: String,
+    pub change_commitment: String,
     pub recipient: String,
     pub relayer: String,
+    pub splits_hash: String,
+    pub denomination: u64,
+    pub amount: u64,
     pub fee: u64,
     pub refund: u64,
+    pub expires_at: i64,
 
     pub nullifier: String,
     pub secret: String,
@@ -976,24 +1034,36 @@ fn to_hex_vec(v: &Vec<[u8; 32]>) -> Vec<String> {
 }
 
 fn build_prove_request(
+    domain: [u8; 32],
+    tree_index: u32,
     root: [u8; 32],
     nullifier_hash: [u8; 32],
     recipient: [u8; 32],
     relayer: [u8; 32],
+    amount: u64,
     fee: u64,
     refund: u64,
+    expires_at: i64,
     nullifier: BigUint,
     secret: BigUint,
     path_elems: Vec<[u8; 32]>,
     path_inds: Vec<u8>,
 ) -> ProveRequest {
     ProveRequest {
+        domain: to_hex32(&domain),
+        tree_index,
         root: to_hex32(&root),
         nullifier_hash: to_hex32(&nullifier_hash),
+        // full withdrawals to one recipient, no change note or splits
+        change_commitment: to_hex32(&[0u8; 32]),
         recipient: to_hex32(&recipient),
         relayer: to_hex32(&relayer),
+        splits_hash: to_hex32(&[0u8; 32]),
+        denomination: DEPOSIT_AMOUNT,
+        amount,
         fee,
         refund,
+        expires_at,
         nullifier: to_hex32(&biguint_to_32_le_bytes(&nullifier)),
         secret: to_hex32(&biguint_to_32_le_bytes(&secret)),
         path_elements: to_hex_vec(&path_elems),
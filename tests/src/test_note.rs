@@ -0,0 +1,101 @@
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use solana_mixer::DepositEvent;
+
+use crate::note::{encrypt_note, recover_notes, Note, ViewingKey, ENCRYPTED_NOTE_LEN};
+
+fn note() -> Note {
+    Note {
+        nullifier: [1u8; 32],
+        secret: [2u8; 32],
+        value: 1_000_000_000,
+    }
+}
+
+#[test]
+fn test_note_round_trip() {
+    let key = ViewingKey::generate();
+    let commitment = [3u8; 32];
+
+    let blob = encrypt_note(&key.public(), &commitment, &note());
+    assert_eq!(blob.len(), ENCRYPTED_NOTE_LEN);
+    assert!(blob.len() <= solana_mixer::MAX_ENCRYPTED_NOTE_LEN);
+    assert_eq!(key.decrypt(&commitment, &blob), Some(note()));
+
+    // same key restored from its bytes
+    let restored = ViewingKey::from_bytes(key.to_bytes());
+    assert_eq!(restored.decrypt(&commitment, &blob), Some(note()));
+}
+
+#[test]
+fn test_note_other_key_or_commitment() {
+    let key = ViewingKey::generate();
+    let commitment = [3u8; 32];
+    let blob = encrypt_note(&key.public(), &commitment, &note());
+
+    assert_eq!(ViewingKey::generate().decrypt(&commitment, &blob), None);
+    assert_eq!(key.decrypt(&[4u8; 32], &blob), None);
+
+    let mut tampered = blob.clone();
+    tampered[40] ^= 1;
+    assert_eq!(key.decrypt(&commitment, &tampered), None);
+    assert_eq!(key.decrypt(&commitment, &blob[..blob.len() - 1]), None);
+}
+
+#[test]
+fn test_recover_notes_in_tree_order() {
+    let key = ViewingKey::generate();
+    let change = Note {
+        value: 250_000_000,
+        ..note()
+    };
+    let event =
+        |commitment: [u8; 32], leaf_index: u32, encrypted_note: Option<Vec<u8>>| DepositEvent {
+            pool: Pubkey::default(),
+            commitment,
+            tree_index: 0,
+            leaf_index,
+            depositor: Pubkey::default(),
+            encrypted_note,
+        };
+    let events = vec![
+        event(
+            [5u8; 32],
+            2,
+            Some(encrypt_note(&key.public(), &[5u8; 32], &change)),
+        ),
+        event([4u8; 32], 1, None),
+        event(
+            [6u8; 32],
+            3,
+            Some(encrypt_note(
+                &ViewingKey::generate().public(),
+                &[6u8; 32],
+                &note(),
+            )),
+        ),
+        event(
+            [3u8; 32],
+            0,
+            Some(encrypt_note(&key.public(), &[3u8; 32], &note())),
+        ),
+    ];
+
+    let recovered = recover_notes(events, &key);
+    assert_eq!(recovered.len(), 2);
+    assert_eq!(
+        (
+            recovered[0].note,
+            recovered[0].commitment,
+            recovered[0].leaf_index
+        ),
+        (note(), [3u8; 32], 0)
+    );
+    assert_eq!(
+        (
+            recovered[1].note,
+            recovered[1].commitment,
+            recovered[1].leaf_index
+        ),
+        (change, [5u8; 32], 2)
+    );
+}
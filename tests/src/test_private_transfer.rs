@@ -0,0 +1,119 @@
+//! `private_transfer` in LiteSVM
+use anchor_client::solana_sdk::{pubkey::Pubkey, signer::Signer};
+use litesvm::types::TransactionResult;
+use solana_mixer::{ErrorCode, TransferInputs};
+
+use crate::{
+    svm::{assert_error, commitment, ix, prove, Mixer, DEPOSIT_AMOUNT},
+    utils::{nullifier_address, private_transfer_accounts, withdraw_accounts},
+};
+
+/// Statement moving the note behind `nullifier_hash` to `new_commitment`,
+/// against the latest root of the current tree
+fn transfer_inputs(
+    mixer: &Mixer,
+    nullifier_hash: [u8; 32],
+    new_commitment: [u8; 32],
+) -> TransferInputs {
+    let tree_index = mixer.state().current_tree;
+    TransferInputs {
+        domain: mixer.domain(),
+        tree_index,
+        root: mixer.tree(tree_index).0,
+        nullifier_hash,
+        new_commitment,
+        expires_at: 0,
+    }
+}
+
+/// `private_transfer` of `public_inputs`, which the mock verifier accepts
+/// whatever kind of statement they are
+fn private_transfer(
+    mixer: &mut Mixer,
+    nullifier_hash: [u8; 32],
+    public_inputs: Vec<u8>,
+) -> TransactionResult {
+    let current_tree = mixer.state().current_tree;
+    let transfer = ix(
+        private_transfer_accounts(
+            mixer.pool,
+            current_tree,
+            current_tree,
+            mixer.user.pubkey(),
+            &nullifier_hash,
+        ),
+        solana_mixer::instruction::PrivateTransfer {
+            nullifier_bytes: nullifier_hash,
+            proof: prove(&public_inputs),
+            public_inputs,
+            encrypted_note: None,
+        },
+    );
+    mixer.send_user(transfer)
+}
+
+#[test]
+fn test_private_transfer_keeps_the_value_in_the_pool() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let pool_before = mixer.lamports(&mixer.pool);
+    let (root_before, _) = mixer.tree(0);
+
+    let inputs = transfer_inputs(&mixer, [1u8; 32], commitment(2));
+    private_transfer(&mut mixer, [1u8; 32], inputs.encode()).unwrap();
+    assert_eq!(mixer.lamports(&mixer.pool), pool_before);
+    let (root, next_index) = mixer.tree(0);
+    assert_eq!(next_index, 2);
+    assert_ne!(root, root_before);
+    assert!(mixer
+        .svm
+        .get_account(&nullifier_address(&mixer.pool, &[1u8; 32]))
+        .is_some());
+
+    // the old note is gone, the new one can be withdrawn
+    assert_error(
+        private_transfer(&mut mixer, [1u8; 32], inputs.encode()),
+        ErrorCode::NullifierAlreadyUsed,
+    );
+    let recipient = Pubkey::new_unique();
+    let inputs = mixer.withdraw_inputs([2u8; 32], recipient, Pubkey::new_unique());
+    mixer.withdraw(&inputs).unwrap();
+    assert!(mixer.lamports(&recipient) > 0);
+}
+
+#[test]
+fn test_proof_kinds_do_not_cross() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+
+    // a withdrawal statement handed to private_transfer
+    let withdrawal = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    assert_error(
+        private_transfer(&mut mixer, [1u8; 32], withdrawal.encode()),
+        ErrorCode::InvalidInput,
+    );
+
+    // a transfer statement handed to withdraw
+    let transfer = transfer_inputs(&mixer, [1u8; 32], commitment(2)).encode();
+    let withdraw = ix(
+        withdraw_accounts(
+            mixer.pool,
+            0,
+            mixer.user.pubkey(),
+            withdrawal.recipient,
+            withdrawal.relayer,
+            &[1u8; 32],
+        ),
+        solana_mixer::instruction::Withdraw {
+            nullifier_bytes: [1u8; 32],
+            proof: prove(&transfer),
+            public_inputs: transfer,
+            change_note: None,
+            splits: vec![],
+        },
+    );
+    assert_error(mixer.send_user(withdraw), ErrorCode::InvalidInput);
+
+    // the note is still unspent
+    mixer.withdraw(&withdrawal).unwrap();
+}
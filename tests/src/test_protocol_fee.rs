@@ -0,0 +1,149 @@
+//! Protocol fee and treasury of a SOL pool in LiteSVM
+use anchor_client::solana_sdk::{pubkey::Pubkey, signer::Signer};
+use litesvm::types::TransactionResult;
+use solana_mixer::{ErrorCode, Nullifier, Treasury, MAX_PROTOCOL_FEE_BPS};
+
+use crate::{
+    svm::{assert_error, commitment, ix, Mixer, DEPOSIT_AMOUNT},
+    utils::treasury_address,
+};
+
+/// 5% of `DEPOSIT_AMOUNT`
+const FEE_CAP: u64 = DEPOSIT_AMOUNT * MAX_PROTOCOL_FEE_BPS as u64 / 10_000;
+
+fn set_protocol_fee(mixer: &mut Mixer, fee_bps: u16, fee_fixed: u64) -> TransactionResult {
+    let set = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::SetProtocolFee { fee_bps, fee_fixed },
+    );
+    mixer.send_admin(set)
+}
+
+fn withdraw_treasury(mixer: &mut Mixer, destination: Pubkey, amount: u64) -> TransactionResult {
+    let withdraw = ix(
+        solana_mixer::accounts::WithdrawTreasury {
+            state: mixer.pool,
+            administrator: mixer.admin.pubkey(),
+            treasury: Some(treasury_address(&mixer.pool)),
+            destination: Some(destination),
+            mint: None,
+            treasury_token_account: None,
+            destination_token_account: None,
+            token_program: None,
+        },
+        solana_mixer::instruction::WithdrawTreasury { amount },
+    );
+    mixer.send_admin(withdraw)
+}
+
+#[test]
+fn test_protocol_fee_is_capped() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    assert_error(
+        set_protocol_fee(&mut mixer, MAX_PROTOCOL_FEE_BPS + 1, 0),
+        ErrorCode::ProtocolFeeTooHigh,
+    );
+    // the flat part counts towards the cap
+    assert_error(
+        set_protocol_fee(&mut mixer, 0, FEE_CAP + 1),
+        ErrorCode::ProtocolFeeTooHigh,
+    );
+    assert_error(
+        set_protocol_fee(&mut mixer, MAX_PROTOCOL_FEE_BPS, 1),
+        ErrorCode::ProtocolFeeTooHigh,
+    );
+    let state = mixer.state();
+    assert_eq!((state.protocol_fee_bps, state.protocol_fee_fixed), (0, 0));
+
+    // right at the cap, split between both parts
+    set_protocol_fee(&mut mixer, 400, FEE_CAP / 5).unwrap();
+    let state = mixer.state();
+    assert_eq!(
+        (state.protocol_fee_bps, state.protocol_fee_fixed),
+        (400, FEE_CAP / 5)
+    );
+}
+
+#[test]
+fn test_protocol_fee_is_bps_of_the_amount_plus_the_flat_part() {
+    const FEE_BPS: u16 = 100;
+    const FEE_FIXED: u64 = 1_000_000;
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    set_protocol_fee(&mut mixer, FEE_BPS, FEE_FIXED).unwrap();
+    mixer.deposit(commitment(1)).unwrap();
+    mixer.deposit(commitment(2)).unwrap();
+    let treasury = treasury_address(&mixer.pool);
+    let rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Nullifier::SPACE);
+
+    let recipient = Pubkey::new_unique();
+    let inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+    let treasury_before = mixer.lamports(&treasury);
+    mixer.withdraw(&inputs).unwrap();
+    let protocol_fee = DEPOSIT_AMOUNT / 100 + FEE_FIXED;
+    assert_eq!(mixer.lamports(&treasury), treasury_before + protocol_fee);
+    assert_eq!(
+        mixer.lamports(&recipient),
+        DEPOSIT_AMOUNT - protocol_fee - rent
+    );
+
+    // a partial withdrawal pays the bps of what it takes out, the flat part in full
+    let recipient = Pubkey::new_unique();
+    let mut inputs = mixer.withdraw_inputs([2u8; 32], recipient, Pubkey::new_unique());
+    inputs.amount = DEPOSIT_AMOUNT / 2;
+    inputs.change_commitment = commitment(3);
+    let treasury_before = mixer.lamports(&treasury);
+    mixer.withdraw(&inputs).unwrap();
+    let protocol_fee = DEPOSIT_AMOUNT / 2 / 100 + FEE_FIXED;
+    assert_eq!(mixer.lamports(&treasury), treasury_before + protocol_fee);
+    assert_eq!(
+        mixer.lamports(&recipient),
+        DEPOSIT_AMOUNT / 2 - protocol_fee - rent
+    );
+}
+
+#[test]
+fn test_amount_below_the_fees_is_too_small() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    set_protocol_fee(&mut mixer, 0, FEE_CAP).unwrap();
+    mixer.deposit(commitment(1)).unwrap();
+
+    // a partial withdrawal smaller than the flat fee
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    inputs.amount = FEE_CAP - 1;
+    inputs.change_commitment = commitment(2);
+    assert_error(mixer.withdraw(&inputs), ErrorCode::AmountTooSmall);
+
+    // or one that covers the fee but not the nullifier rent
+    inputs.amount = FEE_CAP;
+    assert_error(mixer.withdraw(&inputs), ErrorCode::AmountTooSmall);
+}
+
+#[test]
+fn test_withdraw_treasury_keeps_the_rent_exempt_minimum() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    set_protocol_fee(&mut mixer, 0, FEE_CAP).unwrap();
+    mixer.deposit(commitment(1)).unwrap();
+    let inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    mixer.withdraw(&inputs).unwrap();
+
+    let treasury = treasury_address(&mixer.pool);
+    let rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Treasury::SPACE);
+    assert_eq!(mixer.lamports(&treasury), rent + FEE_CAP);
+
+    let destination = Pubkey::new_unique();
+    assert_error(
+        withdraw_treasury(&mut mixer, destination, FEE_CAP + 1),
+        ErrorCode::InsufficientTreasury,
+    );
+    withdraw_treasury(&mut mixer, destination, FEE_CAP).unwrap();
+    assert_eq!(mixer.lamports(&destination), FEE_CAP);
+    assert_eq!(mixer.lamports(&treasury), rent);
+    assert_error(
+        withdraw_treasury(&mut mixer, destination, 1),
+        ErrorCode::InsufficientTreasury,
+    );
+}
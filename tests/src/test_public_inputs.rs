@@ -0,0 +1,131 @@
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use solana_mixer::{
+    hash_splits, public_inputs::PUBLIC_INPUTS_VERSION, ProofKind, PublicInputs, TransactInputs,
+    TransferInputs, WithdrawSplit,
+};
+
+fn sample() -> PublicInputs {
+    PublicInputs {
+        domain: [9u8; 32],
+        tree_index: 3,
+        root: [1u8; 32],
+        nullifier_hash: [2u8; 32],
+        change_commitment: [4u8; 32],
+        recipient: Pubkey::new_unique(),
+        relayer: Pubkey::new_unique(),
+        splits_hash: [5u8; 32],
+        denomination: 1_000_000_000,
+        amount: 400_000_000,
+        fee: 5_000,
+        refund: 0,
+        expires_at: 1_700_000_000,
+    }
+}
+
+#[test]
+fn test_public_inputs_roundtrip() {
+    let inputs = sample();
+    let bytes = inputs.encode();
+
+    assert_eq!(bytes.len(), PublicInputs::LEN);
+    assert_eq!(bytes[0], PUBLIC_INPUTS_VERSION);
+    assert_eq!(bytes[1], ProofKind::Withdraw as u8);
+    assert_eq!(PublicInputs::decode(&bytes).unwrap(), inputs);
+}
+
+#[test]
+fn test_public_inputs_rejects_bad_length() {
+    let mut bytes = sample().encode();
+    bytes.push(0);
+    assert!(PublicInputs::decode(&bytes).is_err());
+
+    bytes.truncate(PublicInputs::LEN - 1);
+    assert!(PublicInputs::decode(&bytes).is_err());
+}
+
+#[test]
+fn test_public_inputs_rejects_unknown_version() {
+    let mut bytes = sample().encode();
+    bytes[0] = PUBLIC_INPUTS_VERSION + 1;
+    assert!(PublicInputs::decode(&bytes).is_err());
+}
+
+#[test]
+fn test_hash_splits_binds_payees_amounts_and_order() {
+    let a = WithdrawSplit {
+        recipient: Pubkey::new_unique(),
+        amount: 100,
+    };
+    let b = WithdrawSplit {
+        recipient: Pubkey::new_unique(),
+        amount: 200,
+    };
+    let hash = hash_splits(&[a, b]);
+
+    assert_eq!(hash, hash_splits(&[a, b]));
+    assert_ne!(hash, hash_splits(&[b, a]));
+    assert_ne!(hash, hash_splits(&[a]));
+    assert_ne!(hash, hash_splits(&[a, WithdrawSplit { amount: 201, ..b }]));
+    // an empty list still hashes, but `withdraw` reads a zero hash as no splits
+    assert_ne!(hash_splits(&[]), [0u8; 32]);
+}
+
+fn transact_sample() -> TransactInputs {
+    TransactInputs {
+        domain: [9u8; 32],
+        tree_index: 1,
+        root: [1u8; 32],
+        input_nullifiers: [[2u8; 32], [0u8; 32]],
+        output_commitments: [[3u8; 32], [4u8; 32]],
+        ext_amount: -250_000,
+        fee: 5_000,
+        recipient: Pubkey::new_unique(),
+        relayer: Pubkey::new_unique(),
+        expires_at: 0,
+    }
+}
+
+#[test]
+fn test_transact_inputs_roundtrip() {
+    let inputs = transact_sample();
+    let bytes = inputs.encode();
+
+    assert_eq!(bytes.len(), TransactInputs::LEN);
+    assert_eq!(bytes[1], ProofKind::Transact as u8);
+    assert_eq!(TransactInputs::decode(&bytes).unwrap(), inputs);
+}
+
+fn transfer_sample() -> TransferInputs {
+    TransferInputs {
+        domain: [9u8; 32],
+        tree_index: 0,
+        root: [1u8; 32],
+        nullifier_hash: [2u8; 32],
+        new_commitment: [3u8; 32],
+        expires_at: 1_700_000_000,
+    }
+}
+
+#[test]
+fn test_transfer_inputs_roundtrip() {
+    let inputs = transfer_sample();
+    let bytes = inputs.encode();
+
+    assert_eq!(bytes.len(), TransferInputs::LEN);
+    assert_eq!(bytes[1], ProofKind::Transfer as u8);
+    assert_eq!(TransferInputs::decode(&bytes).unwrap(), inputs);
+}
+
+#[test]
+fn test_public_inputs_kinds_do_not_mix() {
+    // no statement decodes as another
+    assert!(PublicInputs::decode(&transact_sample().encode()).is_err());
+    assert!(PublicInputs::decode(&transfer_sample().encode()).is_err());
+    assert!(TransactInputs::decode(&sample().encode()).is_err());
+    assert!(TransferInputs::decode(&sample().encode()).is_err());
+
+    // the kind byte is checked on its own, not only implied by the length
+    let mut bytes = sample().encode();
+    bytes[1] = ProofKind::Transfer as u8;
+    assert!(PublicInputs::decode(&bytes).is_err());
+}
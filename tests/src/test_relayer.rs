@@ -0,0 +1,368 @@
+//! Relayer fee caps, the relayer registry and stakes in LiteSVM
+use anchor_client::{
+    anchor_lang::AccountDeserialize,
+    solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, system_program},
+};
+use litesvm::types::TransactionResult;
+use solana_mixer::{ErrorCode, RelayerEntry, RELAYER_UNBONDING_PERIOD};
+
+use crate::{
+    svm::{assert_error, commitment, ix, prove, Mixer, DEPOSIT_AMOUNT},
+    utils::{relayer_entry_address, withdraw_accounts},
+};
+
+/// 0.01 SOL
+const FEE: u64 = 10_000_000;
+/// 0.5 SOL
+const STAKE: u64 = 500_000_000;
+
+fn set_relayer_policy(
+    mixer: &mut Mixer,
+    max_fee: u64,
+    min_stake: u64,
+    registered_only: bool,
+) -> TransactionResult {
+    let set = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::SetRelayerPolicy {
+            max_fee,
+            min_stake,
+            registered_only,
+        },
+    );
+    mixer.send_admin(set)
+}
+
+fn register(mixer: &mut Mixer, relayer: &Keypair, max_fee: u64) {
+    let register = ix(
+        solana_mixer::accounts::RegisterRelayer {
+            state: mixer.pool,
+            relayer_entry: relayer_entry_address(&mixer.pool, &relayer.pubkey()),
+            relayer: relayer.pubkey(),
+            system_program: system_program::ID,
+        },
+        solana_mixer::instruction::RegisterRelayer {
+            url: "https://relayer.example".to_string(),
+            max_fee,
+        },
+    );
+    mixer.send(&[register], &[relayer]).unwrap();
+}
+
+fn approve(mixer: &mut Mixer, relayer: &Pubkey, approved: bool) {
+    let approve = ix(
+        solana_mixer::accounts::ApproveRelayer {
+            state: mixer.pool,
+            administrator: mixer.admin.pubkey(),
+            relayer_entry: relayer_entry_address(&mixer.pool, relayer),
+        },
+        solana_mixer::instruction::ApproveRelayer { approved },
+    );
+    mixer.send_admin(approve).unwrap();
+}
+
+fn deregister(mixer: &mut Mixer, relayer: &Keypair) -> TransactionResult {
+    let deregister = ix(
+        solana_mixer::accounts::DeregisterRelayer {
+            relayer_entry: relayer_entry_address(&mixer.pool, &relayer.pubkey()),
+            relayer: relayer.pubkey(),
+        },
+        solana_mixer::instruction::DeregisterRelayer {},
+    );
+    mixer.send(&[deregister], &[relayer])
+}
+
+fn withdraw_stake(mixer: &mut Mixer, relayer: &Keypair) -> TransactionResult {
+    let withdraw = ix(
+        solana_mixer::accounts::WithdrawStake {
+            relayer_entry: relayer_entry_address(&mixer.pool, &relayer.pubkey()),
+            relayer: relayer.pubkey(),
+        },
+        solana_mixer::instruction::WithdrawStake {},
+    );
+    // paid by the user, so the relayer's balance only moves by the entry
+    let user = mixer.user.insecure_clone();
+    mixer.send(&[withdraw], &[&user, relayer])
+}
+
+fn top_up(mixer: &mut Mixer, relayer: &Keypair, amount: u64) -> TransactionResult {
+    let top_up = ix(
+        solana_mixer::accounts::TopUpStake {
+            relayer_entry: relayer_entry_address(&mixer.pool, &relayer.pubkey()),
+            relayer: relayer.pubkey(),
+            system_program: system_program::ID,
+        },
+        solana_mixer::instruction::TopUpStake { amount },
+    );
+    mixer.send(&[top_up], &[relayer])
+}
+
+fn slash(
+    mixer: &mut Mixer,
+    administrator: &Keypair,
+    relayer: &Pubkey,
+    destination: Pubkey,
+    amount: u64,
+) -> TransactionResult {
+    let slash = ix(
+        solana_mixer::accounts::SlashRelayer {
+            state: mixer.pool,
+            administrator: administrator.pubkey(),
+            relayer_entry: relayer_entry_address(&mixer.pool, relayer),
+            destination,
+        },
+        solana_mixer::instruction::SlashRelayer { amount },
+    );
+    mixer.send(&[slash], &[administrator])
+}
+
+fn entry(mixer: &Mixer, relayer: &Pubkey) -> Option<RelayerEntry> {
+    let account = mixer
+        .svm
+        .get_account(&relayer_entry_address(&mixer.pool, relayer))?;
+    Some(RelayerEntry::try_deserialize(&mut &account.data[..]).unwrap())
+}
+
+/// Deposit a note and withdraw it paying `relayer` a `fee`, passing its
+/// registry entry when `with_entry`
+fn withdraw_paying(
+    mixer: &mut Mixer,
+    seed: u8,
+    relayer: &Pubkey,
+    fee: u64,
+    with_entry: bool,
+) -> TransactionResult {
+    mixer.deposit(commitment(seed)).unwrap();
+    let mut inputs = mixer.withdraw_inputs([seed; 32], Pubkey::new_unique(), *relayer);
+    inputs.fee = fee;
+    let public_inputs = inputs.encode();
+    let withdraw = ix(
+        solana_mixer::accounts::Withdraw {
+            relayer_entry: with_entry.then(|| relayer_entry_address(&mixer.pool, relayer)),
+            ..withdraw_accounts(
+                mixer.pool,
+                inputs.tree_index,
+                mixer.user.pubkey(),
+                inputs.recipient,
+                inputs.relayer,
+                &inputs.nullifier_hash,
+            )
+        },
+        solana_mixer::instruction::Withdraw {
+            nullifier_bytes: inputs.nullifier_hash,
+            proof: prove(&public_inputs),
+            public_inputs,
+            change_note: None,
+            splits: vec![],
+        },
+    );
+    mixer.send_user(withdraw)
+}
+
+#[test]
+fn test_pool_caps_the_relayer_fee() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    let relayer = mixer.funded(DEPOSIT_AMOUNT).pubkey();
+    // never above the deposit itself
+    assert_error(
+        set_relayer_policy(&mut mixer, DEPOSIT_AMOUNT + 1, 0, false),
+        ErrorCode::FeeTooHigh,
+    );
+    set_relayer_policy(&mut mixer, FEE, 0, false).unwrap();
+
+    assert_error(
+        withdraw_paying(&mut mixer, 1, &relayer, FEE + 1, false),
+        ErrorCode::FeeTooHigh,
+    );
+    // any account may relay in an open pool
+    let before = mixer.lamports(&relayer);
+    withdraw_paying(&mut mixer, 2, &relayer, FEE, false).unwrap();
+    assert_eq!(mixer.lamports(&relayer), before + FEE);
+}
+
+#[test]
+fn test_registered_only_pools_pay_approved_relayers() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    set_relayer_policy(&mut mixer, 2 * FEE, 0, true).unwrap();
+    let relayer = mixer.funded(DEPOSIT_AMOUNT);
+
+    // unknown relayers take no fee, but users can still withdraw on their own
+    assert_error(
+        withdraw_paying(&mut mixer, 1, &relayer.pubkey(), FEE, false),
+        ErrorCode::RelayerNotRegistered,
+    );
+    withdraw_paying(&mut mixer, 2, &relayer.pubkey(), 0, false).unwrap();
+
+    // registered without a stake, the entry waits for approval
+    register(&mut mixer, &relayer, FEE);
+    assert_error(
+        withdraw_paying(&mut mixer, 3, &relayer.pubkey(), FEE, true),
+        ErrorCode::RelayerNotRegistered,
+    );
+    approve(&mut mixer, &relayer.pubkey(), true);
+    withdraw_paying(&mut mixer, 4, &relayer.pubkey(), FEE, true).unwrap();
+    // the relayer's own cap is below the pool's
+    assert_error(
+        withdraw_paying(&mut mixer, 5, &relayer.pubkey(), FEE + 1, true),
+        ErrorCode::FeeTooHigh,
+    );
+
+    approve(&mut mixer, &relayer.pubkey(), false);
+    assert_error(
+        withdraw_paying(&mut mixer, 6, &relayer.pubkey(), FEE, true),
+        ErrorCode::RelayerNotRegistered,
+    );
+}
+
+#[test]
+fn test_stake_is_locked_after_deregistering() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    set_relayer_policy(&mut mixer, FEE, STAKE, true).unwrap();
+    let relayer = mixer.funded(DEPOSIT_AMOUNT);
+    mixer.warp_to(1_700_000_000);
+
+    // the stake makes the entry active without approval
+    register(&mut mixer, &relayer, FEE);
+    assert_eq!(entry(&mixer, &relayer.pubkey()).unwrap().stake, STAKE);
+    withdraw_paying(&mut mixer, 1, &relayer.pubkey(), FEE, true).unwrap();
+    assert_error(withdraw_stake(&mut mixer, &relayer), ErrorCode::StakeLocked);
+
+    deregister(&mut mixer, &relayer).unwrap();
+    let deregistered_at = mixer.now();
+    assert_eq!(
+        entry(&mixer, &relayer.pubkey()).unwrap().deregistered_at,
+        Some(deregistered_at)
+    );
+    assert_error(
+        deregister(&mut mixer, &relayer),
+        ErrorCode::RelayerNotRegistered,
+    );
+    assert_error(
+        withdraw_paying(&mut mixer, 2, &relayer.pubkey(), FEE, true),
+        ErrorCode::RelayerNotRegistered,
+    );
+
+    mixer.warp_to(deregistered_at + RELAYER_UNBONDING_PERIOD - 1);
+    assert_error(withdraw_stake(&mut mixer, &relayer), ErrorCode::StakeLocked);
+
+    mixer.warp_to(deregistered_at + RELAYER_UNBONDING_PERIOD);
+    let entry_lamports = mixer.lamports(&relayer_entry_address(&mixer.pool, &relayer.pubkey()));
+    let before = mixer.lamports(&relayer.pubkey());
+    withdraw_stake(&mut mixer, &relayer).unwrap();
+    assert_eq!(mixer.lamports(&relayer.pubkey()), before + entry_lamports);
+    assert!(entry(&mixer, &relayer.pubkey()).is_none());
+}
+
+#[test]
+fn test_deregistering_at_the_epoch_locks_the_stake() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    set_relayer_policy(&mut mixer, FEE, STAKE, true).unwrap();
+    let relayer = mixer.funded(DEPOSIT_AMOUNT);
+    mixer.warp_to(0);
+
+    register(&mut mixer, &relayer, FEE);
+    deregister(&mut mixer, &relayer).unwrap();
+    assert_eq!(
+        entry(&mixer, &relayer.pubkey()).unwrap().deregistered_at,
+        Some(0)
+    );
+    assert_error(
+        deregister(&mut mixer, &relayer),
+        ErrorCode::RelayerNotRegistered,
+    );
+    assert_error(
+        withdraw_paying(&mut mixer, 1, &relayer.pubkey(), FEE, true),
+        ErrorCode::RelayerNotRegistered,
+    );
+    assert_error(withdraw_stake(&mut mixer, &relayer), ErrorCode::StakeLocked);
+
+    mixer.warp_to(RELAYER_UNBONDING_PERIOD);
+    withdraw_stake(&mut mixer, &relayer).unwrap();
+    assert!(entry(&mixer, &relayer.pubkey()).is_none());
+}
+
+#[test]
+fn test_top_up_keeps_a_relayer_active_after_the_minimum_rises() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    set_relayer_policy(&mut mixer, FEE, STAKE, true).unwrap();
+    let relayer = mixer.funded(2 * DEPOSIT_AMOUNT);
+    register(&mut mixer, &relayer, FEE);
+
+    set_relayer_policy(&mut mixer, FEE, 2 * STAKE, true).unwrap();
+    assert_error(
+        withdraw_paying(&mut mixer, 1, &relayer.pubkey(), FEE, true),
+        ErrorCode::RelayerNotRegistered,
+    );
+
+    assert_error(top_up(&mut mixer, &relayer, 0), ErrorCode::InvalidInput);
+    let entry_before = mixer.lamports(&relayer_entry_address(&mixer.pool, &relayer.pubkey()));
+    top_up(&mut mixer, &relayer, STAKE).unwrap();
+    assert_eq!(entry(&mixer, &relayer.pubkey()).unwrap().stake, 2 * STAKE);
+    assert_eq!(
+        mixer.lamports(&relayer_entry_address(&mixer.pool, &relayer.pubkey())),
+        entry_before + STAKE
+    );
+    withdraw_paying(&mut mixer, 2, &relayer.pubkey(), FEE, true).unwrap();
+
+    // a deregistered entry only unbonds
+    deregister(&mut mixer, &relayer).unwrap();
+    assert_error(
+        top_up(&mut mixer, &relayer, STAKE),
+        ErrorCode::RelayerNotRegistered,
+    );
+}
+
+#[test]
+fn test_administrator_slashes_the_stake() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    set_relayer_policy(&mut mixer, FEE, STAKE, true).unwrap();
+    let relayer = mixer.funded(DEPOSIT_AMOUNT);
+    register(&mut mixer, &relayer, FEE);
+    let destination = Pubkey::new_unique();
+
+    let stranger = mixer.funded(DEPOSIT_AMOUNT);
+    assert_error(
+        slash(&mut mixer, &stranger, &relayer.pubkey(), destination, STAKE),
+        ErrorCode::Unauthorized,
+    );
+    let admin = mixer.admin.insecure_clone();
+    assert_error(
+        slash(
+            &mut mixer,
+            &admin,
+            &relayer.pubkey(),
+            destination,
+            STAKE + 1,
+        ),
+        ErrorCode::SlashTooLarge,
+    );
+
+    // below the minimum stake the relayer is no longer active
+    slash(
+        &mut mixer,
+        &admin,
+        &relayer.pubkey(),
+        destination,
+        STAKE / 2,
+    )
+    .unwrap();
+    assert_eq!(mixer.lamports(&destination), STAKE / 2);
+    assert_eq!(entry(&mixer, &relayer.pubkey()).unwrap().stake, STAKE / 2);
+    assert_error(
+        withdraw_paying(&mut mixer, 1, &relayer.pubkey(), FEE, true),
+        ErrorCode::RelayerNotRegistered,
+    );
+
+    // still slashable while unbonding
+    deregister(&mut mixer, &relayer).unwrap();
+    slash(
+        &mut mixer,
+        &admin,
+        &relayer.pubkey(),
+        destination,
+        STAKE / 2,
+    )
+    .unwrap();
+    assert_eq!(mixer.lamports(&destination), STAKE);
+    assert_eq!(entry(&mixer, &relayer.pubkey()).unwrap().stake, 0);
+}
@@ -0,0 +1,487 @@
+//! Token pools in LiteSVM: plain SPL Token and Token-2022 mints, transfer
+//! fees and the mints `initialize_token_pool` refuses.
+use anchor_client::{
+    anchor_lang::AccountDeserialize,
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::Keypair,
+        signer::Signer,
+        system_instruction,
+    },
+};
+use anchor_spl::{
+    associated_token::spl_associated_token_account::instruction::create_associated_token_account,
+    token::spl_token,
+    token_interface::{
+        spl_token_2022::{
+            self,
+            extension::{transfer_fee, transfer_hook, ExtensionType},
+        },
+        TokenAccount,
+    },
+};
+use solana_mixer::{hash_splits, ErrorCode, Nullifier, PublicInputs, WithdrawSplit};
+
+use crate::{
+    svm::{assert_error, commitment, ix, prove, Mixer},
+    utils::{
+        associated_token_address, token_deposit_accounts, token_withdraw_accounts,
+        token_withdraw_to_new_account_accounts, vault_address, TokenPoolAccounts,
+    },
+};
+
+const DECIMALS: u8 = 6;
+/// 1 token
+const DEPOSIT_AMOUNT: u64 = 1_000_000;
+/// Token-2022 transfer fee of the fee-charging mint (1%)
+const TRANSFER_FEE_BPS: u16 = 100;
+
+/// Create a mint owned by `token_program` with `extensions`, initialized by
+/// `init_extensions` before the mint itself, `mixer.admin` as mint authority
+fn create_mint(
+    mixer: &mut Mixer,
+    token_program: Pubkey,
+    extensions: &[ExtensionType],
+    init_extensions: impl FnOnce(&Pubkey) -> Vec<Instruction>,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let space = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(extensions)
+        .unwrap();
+    let mut instructions = vec![system_instruction::create_account(
+        &mixer.admin.pubkey(),
+        &mint.pubkey(),
+        mixer.svm.minimum_balance_for_rent_exemption(space),
+        space as u64,
+        &token_program,
+    )];
+    instructions.extend(init_extensions(&mint.pubkey()));
+    instructions.push(
+        spl_token_2022::instruction::initialize_mint2(
+            &token_program,
+            &mint.pubkey(),
+            &mixer.admin.pubkey(),
+            None,
+            DECIMALS,
+        )
+        .unwrap(),
+    );
+    let admin = mixer.admin.insecure_clone();
+    mixer.send(&instructions, &[&admin, &mint]).unwrap();
+    mint.pubkey()
+}
+
+/// Associated token account of `owner`, minted `amount` tokens
+fn token_account(mixer: &mut Mixer, token_program: Pubkey, owner: &Pubkey, amount: u64) -> Pubkey {
+    let admin = mixer.admin.insecure_clone();
+    let mut instructions = vec![create_associated_token_account(
+        &admin.pubkey(),
+        owner,
+        &mixer.mint,
+        &token_program,
+    )];
+    let address = associated_token_address(owner, &mixer.mint, &token_program);
+    if amount > 0 {
+        instructions.push(
+            spl_token_2022::instruction::mint_to(
+                &token_program,
+                &mixer.mint,
+                &address,
+                &admin.pubkey(),
+                &[],
+                amount,
+            )
+            .unwrap(),
+        );
+    }
+    mixer.send(&instructions, &[&admin]).unwrap();
+    address
+}
+
+fn balance(mixer: &Mixer, token_account: &Pubkey) -> u64 {
+    let account = mixer.svm.get_account(token_account).unwrap();
+    TokenAccount::try_deserialize(&mut &account.data[..])
+        .unwrap()
+        .amount
+}
+
+/// Deposit one note from `user`, whose token account is created and funded
+/// on first use
+fn deposit(mixer: &mut Mixer, token_program: Pubkey, seed: u8) {
+    let user = mixer.user.pubkey();
+    let user_token_account = associated_token_address(&user, &mixer.mint, &token_program);
+    if mixer.svm.get_account(&user_token_account).is_none() {
+        token_account(mixer, token_program, &user, 10 * DEPOSIT_AMOUNT);
+    }
+    let deposit = ix(
+        token_deposit_accounts(
+            mixer.pool,
+            0,
+            user,
+            &TokenPoolAccounts {
+                mint: mixer.mint,
+                token_program,
+                user_token_account,
+                relayer_token_account: None,
+            },
+        ),
+        solana_mixer::instruction::Deposit {
+            commitment: commitment(seed),
+            encrypted_note: None,
+            record_bump: None,
+        },
+    );
+    mixer.send_user(deposit).unwrap();
+}
+
+/// `withdraw` of a token pool proving `inputs`, submitted by `user`; split
+/// payees are paid into `payee_token_accounts`
+fn withdraw_ix(
+    mixer: &Mixer,
+    token_program: Pubkey,
+    inputs: &PublicInputs,
+    recipient_token_account: Pubkey,
+    relayer_token_account: Option<Pubkey>,
+    splits: &[WithdrawSplit],
+    payee_token_accounts: &[Pubkey],
+) -> Instruction {
+    let public_inputs = inputs.encode();
+    let mut withdraw = ix(
+        token_withdraw_accounts(
+            mixer.pool,
+            inputs.tree_index,
+            mixer.user.pubkey(),
+            inputs.recipient,
+            inputs.relayer,
+            &inputs.nullifier_hash,
+            &TokenPoolAccounts {
+                mint: mixer.mint,
+                token_program,
+                user_token_account: recipient_token_account,
+                relayer_token_account,
+            },
+        ),
+        solana_mixer::instruction::Withdraw {
+            nullifier_bytes: inputs.nullifier_hash,
+            proof: prove(&public_inputs),
+            public_inputs,
+            change_note: None,
+            splits: splits.to_vec(),
+        },
+    );
+    withdraw.accounts.extend(
+        payee_token_accounts
+            .iter()
+            .map(|account| AccountMeta::new(*account, false)),
+    );
+    withdraw
+}
+
+/// Deposit one note, then withdraw it in full to a new recipient, returning
+/// the recipient's token account
+fn deposit_and_withdraw(mixer: &mut Mixer, token_program: Pubkey) -> Pubkey {
+    deposit(mixer, token_program, 1);
+    assert_eq!(balance(mixer, &vault_address(&mixer.pool)), DEPOSIT_AMOUNT);
+
+    let recipient = Pubkey::new_unique();
+    let recipient_token_account = token_account(mixer, token_program, &recipient, 0);
+    let inputs = mixer.withdraw_inputs([9u8; 32], recipient, Pubkey::new_unique());
+    let withdraw = withdraw_ix(
+        mixer,
+        token_program,
+        &inputs,
+        recipient_token_account,
+        None,
+        &[],
+        &[],
+    );
+    mixer.send_user(withdraw).unwrap();
+    recipient_token_account
+}
+
+/// Pool of a plain SPL Token mint
+fn spl_token_pool() -> Mixer {
+    let mut mixer = Mixer::new();
+    let mint = create_mint(&mut mixer, spl_token::ID, &[], |_| vec![]);
+    mixer
+        .init_token_pool(mint, spl_token::ID, DEPOSIT_AMOUNT)
+        .unwrap();
+    mixer
+}
+
+#[test]
+fn test_spl_token_pool_round_trip() {
+    let mut mixer = spl_token_pool();
+
+    let recipient_token_account = deposit_and_withdraw(&mut mixer, spl_token::ID);
+    assert_eq!(balance(&mixer, &recipient_token_account), DEPOSIT_AMOUNT);
+    assert_eq!(balance(&mixer, &vault_address(&mixer.pool)), 0);
+}
+
+#[test]
+fn test_token_2022_transfer_fee_is_paid_by_depositor_and_withheld_from_recipient() {
+    let mut mixer = Mixer::new();
+    let authority = mixer.admin.pubkey();
+    let mint = create_mint(
+        &mut mixer,
+        spl_token_2022::ID,
+        &[ExtensionType::TransferFeeConfig],
+        |mint| {
+            vec![transfer_fee::instruction::initialize_transfer_fee_config(
+                &spl_token_2022::ID,
+                mint,
+                Some(&authority),
+                Some(&authority),
+                TRANSFER_FEE_BPS,
+                u64::MAX,
+            )
+            .unwrap()]
+        },
+    );
+    mixer
+        .init_token_pool(mint, spl_token_2022::ID, DEPOSIT_AMOUNT)
+        .unwrap();
+
+    // the vault holds exactly one deposit (checked inside), the depositor paid more
+    let recipient_token_account = deposit_and_withdraw(&mut mixer, spl_token_2022::ID);
+    let user_token_account =
+        associated_token_address(&mixer.user.pubkey(), &mixer.mint, &spl_token_2022::ID);
+    assert!(balance(&mixer, &user_token_account) < 9 * DEPOSIT_AMOUNT);
+
+    let withheld = DEPOSIT_AMOUNT * TRANSFER_FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        balance(&mixer, &recipient_token_account),
+        DEPOSIT_AMOUNT - withheld
+    );
+    assert_eq!(balance(&mixer, &vault_address(&mixer.pool)), 0);
+}
+
+#[test]
+fn test_transfer_hook_mint_is_unsupported() {
+    let mut mixer = Mixer::new();
+    let authority = mixer.admin.pubkey();
+    let mint = create_mint(
+        &mut mixer,
+        spl_token_2022::ID,
+        &[ExtensionType::TransferHook],
+        |mint| {
+            vec![transfer_hook::instruction::initialize(
+                &spl_token_2022::ID,
+                mint,
+                Some(authority),
+                Some(Pubkey::new_unique()),
+            )
+            .unwrap()]
+        },
+    );
+    assert_error(
+        mixer.init_token_pool(mint, spl_token_2022::ID, DEPOSIT_AMOUNT),
+        ErrorCode::UnsupportedMint,
+    );
+}
+
+#[test]
+fn test_permanent_delegate_mint_is_unsupported() {
+    let mut mixer = Mixer::new();
+    let delegate = mixer.admin.pubkey();
+    let mint = create_mint(
+        &mut mixer,
+        spl_token_2022::ID,
+        &[ExtensionType::PermanentDelegate],
+        |mint| {
+            vec![spl_token_2022::instruction::initialize_permanent_delegate(
+                &spl_token_2022::ID,
+                mint,
+                &delegate,
+            )
+            .unwrap()]
+        },
+    );
+    assert_error(
+        mixer.init_token_pool(mint, spl_token_2022::ID, DEPOSIT_AMOUNT),
+        ErrorCode::UnsupportedMint,
+    );
+}
+
+#[test]
+fn test_token_pool_refund_is_paid_by_the_caller() {
+    let mut mixer = spl_token_pool();
+    deposit(&mut mixer, spl_token::ID, 1);
+
+    // a fresh wallet, which the refund alone makes rent exempt
+    let recipient = Pubkey::new_unique();
+    let recipient_token_account = token_account(&mut mixer, spl_token::ID, &recipient, 0);
+    let refund = mixer.svm.minimum_balance_for_rent_exemption(0);
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+    inputs.refund = refund;
+    let withdraw = withdraw_ix(
+        &mixer,
+        spl_token::ID,
+        &inputs,
+        recipient_token_account,
+        None,
+        &[],
+        &[],
+    );
+
+    // the administrator pays the transaction fee, so the caller's balance only
+    // moves by the refund and the nullifier rent
+    let caller_before = mixer.lamports(&mixer.user.pubkey());
+    let pool_before = mixer.lamports(&mixer.pool);
+    let (admin, user) = (mixer.admin.insecure_clone(), mixer.user.insecure_clone());
+    mixer.send(&[withdraw], &[&admin, &user]).unwrap();
+
+    let nullifier_rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Nullifier::SPACE);
+    assert_eq!(mixer.lamports(&recipient), refund);
+    assert_eq!(
+        mixer.lamports(&mixer.user.pubkey()),
+        caller_before - refund - nullifier_rent
+    );
+    assert_eq!(mixer.lamports(&mixer.pool), pool_before);
+    assert_eq!(balance(&mixer, &recipient_token_account), DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_token_pool_splits_pay_the_payees_token_accounts() {
+    let mut mixer = spl_token_pool();
+    deposit(&mut mixer, spl_token::ID, 1);
+    let recipient = Pubkey::new_unique();
+    let recipient_token_account = token_account(&mut mixer, spl_token::ID, &recipient, 0);
+    let splits = [
+        WithdrawSplit {
+            recipient: Pubkey::new_unique(),
+            amount: DEPOSIT_AMOUNT / 5,
+        },
+        WithdrawSplit {
+            recipient: Pubkey::new_unique(),
+            amount: DEPOSIT_AMOUNT / 4,
+        },
+    ];
+    let payee_token_accounts =
+        splits.map(|split| token_account(&mut mixer, spl_token::ID, &split.recipient, 0));
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+    inputs.splits_hash = hash_splits(&splits);
+
+    // each payee's token account must belong to it
+    let swapped = [payee_token_accounts[1], payee_token_accounts[0]];
+    let withdraw = withdraw_ix(
+        &mixer,
+        spl_token::ID,
+        &inputs,
+        recipient_token_account,
+        None,
+        &splits,
+        &swapped,
+    );
+    assert_error(mixer.send_user(withdraw), ErrorCode::InvalidSplits);
+
+    let withdraw = withdraw_ix(
+        &mixer,
+        spl_token::ID,
+        &inputs,
+        recipient_token_account,
+        None,
+        &splits,
+        &payee_token_accounts,
+    );
+    mixer.send_user(withdraw).unwrap();
+    assert_eq!(
+        balance(&mixer, &payee_token_accounts[0]),
+        DEPOSIT_AMOUNT / 5
+    );
+    assert_eq!(
+        balance(&mixer, &payee_token_accounts[1]),
+        DEPOSIT_AMOUNT / 4
+    );
+    assert_eq!(
+        balance(&mixer, &recipient_token_account),
+        DEPOSIT_AMOUNT - DEPOSIT_AMOUNT / 5 - DEPOSIT_AMOUNT / 4
+    );
+    assert_eq!(balance(&mixer, &vault_address(&mixer.pool)), 0);
+}
+
+/// `withdraw` of an SPL Token pool proving `inputs` without a recipient token
+/// account, so that `withdraw` creates the recipient's associated one
+fn withdraw_to_new_account_ix(mixer: &Mixer, inputs: &PublicInputs) -> Instruction {
+    let public_inputs = inputs.encode();
+    ix(
+        token_withdraw_to_new_account_accounts(
+            mixer.pool,
+            inputs.tree_index,
+            mixer.user.pubkey(),
+            inputs.recipient,
+            inputs.relayer,
+            &inputs.nullifier_hash,
+            &TokenPoolAccounts {
+                mint: mixer.mint,
+                token_program: spl_token::ID,
+                user_token_account: Pubkey::default(),
+                relayer_token_account: None,
+            },
+        ),
+        solana_mixer::instruction::Withdraw {
+            nullifier_bytes: inputs.nullifier_hash,
+            proof: prove(&public_inputs),
+            public_inputs,
+            change_note: None,
+            splits: vec![],
+        },
+    )
+}
+
+#[test]
+fn test_withdraw_creates_the_recipients_associated_token_account() {
+    let mut mixer = spl_token_pool();
+    deposit(&mut mixer, spl_token::ID, 1);
+    deposit(&mut mixer, spl_token::ID, 2);
+
+    let recipient = Pubkey::new_unique();
+    let ata = associated_token_address(&recipient, &mixer.mint, &spl_token::ID);
+    assert!(mixer.svm.get_account(&ata).is_none());
+    let nullifier_rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Nullifier::SPACE);
+    let (admin, user) = (mixer.admin.insecure_clone(), mixer.user.insecure_clone());
+
+    // the administrator pays the transaction fee, so the caller's balance only
+    // moves by the account rent and the nullifier rent
+    let inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+    let caller_before = mixer.lamports(&user.pubkey());
+    mixer
+        .send(
+            &[withdraw_to_new_account_ix(&mixer, &inputs)],
+            &[&admin, &user],
+        )
+        .unwrap();
+    let account = mixer.svm.get_account(&ata).unwrap();
+    assert_eq!(account.owner, spl_token::ID);
+    let ata_rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(account.data.len());
+    assert_eq!(account.lamports, ata_rent);
+    let created = TokenAccount::try_deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(created.owner, recipient);
+    assert_eq!(created.mint, mixer.mint);
+    assert_eq!(created.amount, DEPOSIT_AMOUNT);
+    assert_eq!(
+        mixer.lamports(&user.pubkey()),
+        caller_before - ata_rent - nullifier_rent
+    );
+
+    // once it exists, the next withdrawal only tops it up
+    let inputs = mixer.withdraw_inputs([2u8; 32], recipient, Pubkey::new_unique());
+    let caller_before = mixer.lamports(&user.pubkey());
+    mixer
+        .send(
+            &[withdraw_to_new_account_ix(&mixer, &inputs)],
+            &[&admin, &user],
+        )
+        .unwrap();
+    assert_eq!(balance(&mixer, &ata), 2 * DEPOSIT_AMOUNT);
+    assert_eq!(
+        mixer.lamports(&user.pubkey()),
+        caller_before - nullifier_rent
+    );
+}
@@ -0,0 +1,270 @@
+//! `transact` of a shielded pool in LiteSVM
+use anchor_client::solana_sdk::{pubkey::Pubkey, signer::Signer};
+use litesvm::types::TransactionResult;
+use solana_mixer::{
+    ErrorCode, Nullifier, TransactInputs, DEFAULT_ROOT_HISTORY_SIZE, DEFAULT_TREE_DEPTH,
+};
+
+use crate::{
+    svm::{assert_error, commitment, ix, prove, Mixer, DEPOSIT_AMOUNT},
+    utils::{nullifier_address, transact_accounts, treasury_address},
+};
+
+/// 0.01 SOL
+const FEE: u64 = 10_000_000;
+
+/// SOL pool with a `deposit_amount` of 0
+fn shielded_pool() -> Mixer {
+    let mut mixer = Mixer::new();
+    mixer
+        .init_sol_pool(0, DEFAULT_TREE_DEPTH, DEFAULT_ROOT_HISTORY_SIZE)
+        .unwrap();
+    mixer
+}
+
+/// Statement against the latest root of the current tree, no fee or expiry
+fn transact_inputs(
+    mixer: &Mixer,
+    input_nullifiers: [[u8; 32]; 2],
+    output_commitments: [[u8; 32]; 2],
+    ext_amount: i64,
+    recipient: Pubkey,
+) -> TransactInputs {
+    let tree_index = mixer.state().current_tree;
+    TransactInputs {
+        domain: mixer.domain(),
+        tree_index,
+        root: mixer.tree(tree_index).0,
+        input_nullifiers,
+        output_commitments,
+        ext_amount,
+        fee: 0,
+        recipient,
+        relayer: Pubkey::new_unique(),
+        expires_at: 0,
+    }
+}
+
+fn transact(mixer: &mut Mixer, inputs: &TransactInputs) -> TransactionResult {
+    let public_inputs = inputs.encode();
+    let transact = ix(
+        transact_accounts(
+            mixer.pool,
+            inputs.tree_index,
+            mixer.state().current_tree,
+            mixer.user.pubkey(),
+            inputs.recipient,
+            inputs.relayer,
+            &inputs.input_nullifiers,
+        ),
+        solana_mixer::instruction::Transact {
+            input_nullifiers: inputs.input_nullifiers,
+            proof: prove(&public_inputs),
+            public_inputs,
+            encrypted_outputs: vec![],
+        },
+    );
+    mixer.send_user(transact)
+}
+
+/// Two notes worth `DEPOSIT_AMOUNT` in total, from the caller
+fn shield(mixer: &mut Mixer) {
+    let inputs = transact_inputs(
+        mixer,
+        [[0u8; 32]; 2],
+        [commitment(1), commitment(2)],
+        DEPOSIT_AMOUNT as i64,
+        Pubkey::new_unique(),
+    );
+    transact(mixer, &inputs).unwrap();
+}
+
+#[test]
+fn test_transact_deposit() {
+    let mut mixer = shielded_pool();
+    let pool_before = mixer.lamports(&mixer.pool);
+
+    // one output, the other slot unused
+    let inputs = transact_inputs(
+        &mixer,
+        [[0u8; 32]; 2],
+        [commitment(1), [0u8; 32]],
+        DEPOSIT_AMOUNT as i64,
+        Pubkey::new_unique(),
+    );
+    transact(&mut mixer, &inputs).unwrap();
+    assert_eq!(mixer.lamports(&mixer.pool), pool_before + DEPOSIT_AMOUNT);
+    assert_eq!(mixer.tree(0).1, 1);
+    // unused input slots spend nothing
+    assert!(mixer
+        .svm
+        .get_account(&nullifier_address(&mixer.pool, &[0u8; 32]))
+        .is_none());
+}
+
+#[test]
+fn test_transact_withdraw_pays_recipient_and_relayer() {
+    let mut mixer = shielded_pool();
+    shield(&mut mixer);
+    let pool_before = mixer.lamports(&mixer.pool);
+
+    // spend one note, keep change in a new one
+    let recipient = Pubkey::new_unique();
+    let mut inputs = transact_inputs(
+        &mixer,
+        [[1u8; 32], [0u8; 32]],
+        [commitment(3), [0u8; 32]],
+        -((DEPOSIT_AMOUNT / 4) as i64),
+        recipient,
+    );
+    inputs.fee = FEE;
+    transact(&mut mixer, &inputs).unwrap();
+
+    assert_eq!(mixer.lamports(&recipient), DEPOSIT_AMOUNT / 4);
+    assert_eq!(mixer.lamports(&inputs.relayer), FEE);
+    assert_eq!(
+        mixer.lamports(&mixer.pool),
+        pool_before - DEPOSIT_AMOUNT / 4 - FEE
+    );
+    assert_eq!(mixer.tree(0).1, 3);
+
+    // the caller paid the rent of the one nullifier spent
+    let rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Nullifier::SPACE);
+    let nullifier = nullifier_address(&mixer.pool, &[1u8; 32]);
+    assert_eq!(mixer.lamports(&nullifier), rent);
+    assert!(mixer
+        .svm
+        .get_account(&nullifier_address(&mixer.pool, &[0u8; 32]))
+        .is_none());
+}
+
+#[test]
+fn test_transact_withdraw_pays_the_protocol_fee() {
+    let mut mixer = shielded_pool();
+    shield(&mut mixer);
+    // no deposit amount to cap a flat fee against
+    let set = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::SetProtocolFee {
+            fee_bps: 100,
+            fee_fixed: 1,
+        },
+    );
+    assert_error(mixer.send_admin(set), ErrorCode::ProtocolFeeTooHigh);
+    let set = ix(
+        mixer.admin_only(),
+        solana_mixer::instruction::SetProtocolFee {
+            fee_bps: 100,
+            fee_fixed: 0,
+        },
+    );
+    mixer.send_admin(set).unwrap();
+    let treasury_before = mixer.lamports(&treasury_address(&mixer.pool));
+    let pool_before = mixer.lamports(&mixer.pool);
+
+    let recipient = Pubkey::new_unique();
+    let inputs = transact_inputs(
+        &mixer,
+        [[1u8; 32], [0u8; 32]],
+        [commitment(3), [0u8; 32]],
+        -((DEPOSIT_AMOUNT / 4) as i64),
+        recipient,
+    );
+    transact(&mut mixer, &inputs).unwrap();
+
+    let protocol_fee = DEPOSIT_AMOUNT / 4 / 100;
+    assert_eq!(
+        mixer.lamports(&recipient),
+        DEPOSIT_AMOUNT / 4 - protocol_fee
+    );
+    assert_eq!(
+        mixer.lamports(&treasury_address(&mixer.pool)),
+        treasury_before + protocol_fee
+    );
+    assert_eq!(
+        mixer.lamports(&mixer.pool),
+        pool_before - DEPOSIT_AMOUNT / 4
+    );
+
+    // moving value inside the pool is free
+    let treasury_before = mixer.lamports(&treasury_address(&mixer.pool));
+    let inputs = transact_inputs(
+        &mixer,
+        [[2u8; 32], [0u8; 32]],
+        [commitment(4), [0u8; 32]],
+        0,
+        Pubkey::new_unique(),
+    );
+    transact(&mut mixer, &inputs).unwrap();
+    assert_eq!(
+        mixer.lamports(&treasury_address(&mixer.pool)),
+        treasury_before
+    );
+}
+
+#[test]
+fn test_transact_withdraw_to_a_fresh_recipient_must_be_rent_exempt() {
+    let mut mixer = shielded_pool();
+    shield(&mut mixer);
+    let wallet_rent = mixer.svm.minimum_balance_for_rent_exemption(0);
+
+    let recipient = Pubkey::new_unique();
+    let mut inputs = transact_inputs(
+        &mixer,
+        [[1u8; 32], [0u8; 32]],
+        [commitment(3), [0u8; 32]],
+        -(wallet_rent as i64 - 1),
+        recipient,
+    );
+    assert_error(
+        transact(&mut mixer, &inputs),
+        ErrorCode::RecipientNotRentExempt,
+    );
+
+    inputs.ext_amount = -(wallet_rent as i64);
+    transact(&mut mixer, &inputs).unwrap();
+    assert_eq!(mixer.lamports(&recipient), wallet_rent);
+}
+
+#[test]
+fn test_transact_rejects_a_spent_input() {
+    let mut mixer = shielded_pool();
+    shield(&mut mixer);
+    let inputs = transact_inputs(
+        &mixer,
+        [[1u8; 32], [0u8; 32]],
+        [commitment(3), [0u8; 32]],
+        0,
+        Pubkey::new_unique(),
+    );
+    transact(&mut mixer, &inputs).unwrap();
+
+    // the same note next to an unspent one
+    let inputs = transact_inputs(
+        &mixer,
+        [[2u8; 32], [1u8; 32]],
+        [commitment(4), [0u8; 32]],
+        0,
+        Pubkey::new_unique(),
+    );
+    assert_error(
+        transact(&mut mixer, &inputs),
+        ErrorCode::NullifierAlreadyUsed,
+    );
+}
+
+#[test]
+fn test_transact_is_for_shielded_pools_only() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let inputs = transact_inputs(
+        &mixer,
+        [[1u8; 32], [0u8; 32]],
+        [commitment(2), [0u8; 32]],
+        0,
+        Pubkey::new_unique(),
+    );
+    assert_error(transact(&mut mixer, &inputs), ErrorCode::WrongPoolKind);
+}
@@ -0,0 +1,87 @@
+//! Tree rollover in LiteSVM
+use anchor_client::solana_sdk::{pubkey::Pubkey, signer::Signer, system_program};
+use litesvm::types::TransactionResult;
+use solana_mixer::ErrorCode;
+
+use crate::{
+    svm::{assert_error, commitment, ix, Mixer, DEPOSIT_AMOUNT},
+    utils::tree_address,
+};
+
+/// Two leaves per tree
+const SMALL_TREE_DEPTH: u8 = 1;
+
+/// SOL pool whose trees fill after two deposits
+fn small_tree_pool() -> Mixer {
+    let mut mixer = Mixer::new();
+    mixer
+        .init_sol_pool(DEPOSIT_AMOUNT, SMALL_TREE_DEPTH, 4)
+        .unwrap();
+    mixer
+}
+
+fn rollover(mixer: &mut Mixer) -> TransactionResult {
+    let current_tree = mixer.state().current_tree;
+    let rollover = ix(
+        solana_mixer::accounts::RolloverTree {
+            state: mixer.pool,
+            current_tree: tree_address(&mixer.pool, current_tree),
+            next_tree: tree_address(&mixer.pool, current_tree + 1),
+            payer: mixer.user.pubkey(),
+            system_program: system_program::ID,
+        },
+        solana_mixer::instruction::RolloverTree {},
+    );
+    mixer.send_user(rollover)
+}
+
+#[test]
+fn test_rollover_needs_a_full_tree() {
+    let mut mixer = small_tree_pool();
+    assert_error(rollover(&mut mixer), ErrorCode::TreeNotFull);
+    mixer.deposit(commitment(1)).unwrap();
+    assert_error(rollover(&mut mixer), ErrorCode::TreeNotFull);
+    assert_eq!(mixer.state().current_tree, 0);
+
+    mixer.deposit(commitment(2)).unwrap();
+    assert_error(mixer.deposit(commitment(3)), ErrorCode::TreeFull);
+    rollover(&mut mixer).unwrap();
+    assert_eq!(mixer.state().current_tree, 1);
+}
+
+#[test]
+fn test_deposits_after_rollover_go_to_the_new_tree() {
+    let mut mixer = small_tree_pool();
+    mixer.deposit(commitment(1)).unwrap();
+    mixer.deposit(commitment(2)).unwrap();
+    let (archived_root, _) = mixer.tree(0);
+    rollover(&mut mixer).unwrap();
+
+    mixer.deposit(commitment(3)).unwrap();
+    assert_eq!(mixer.tree(0), (archived_root, 2));
+    let (root, next_index) = mixer.tree(1);
+    assert_eq!(next_index, 1);
+    assert_ne!(root, archived_root);
+}
+
+#[test]
+fn test_withdraw_against_an_archived_root() {
+    let mut mixer = small_tree_pool();
+    mixer.deposit(commitment(1)).unwrap();
+    mixer.deposit(commitment(2)).unwrap();
+    rollover(&mut mixer).unwrap();
+    mixer.deposit(commitment(3)).unwrap();
+
+    // the proof names tree 0 and its last root, the pool is on tree 1
+    let recipient = Pubkey::new_unique();
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+    inputs.tree_index = 0;
+    inputs.root = mixer.tree(0).0;
+    mixer.withdraw(&inputs).unwrap();
+    assert!(mixer.lamports(&recipient) > 0);
+
+    // a root of the new tree is not one of tree 0
+    let mut inputs = mixer.withdraw_inputs([2u8; 32], recipient, Pubkey::new_unique());
+    inputs.tree_index = 0;
+    assert_error(mixer.withdraw(&inputs), ErrorCode::InvalidRoot);
+}
@@ -0,0 +1,152 @@
+//! Known-answer tests for the circom Groth16 backend.
+//!
+//! `tests/fixtures/circom_groth16` holds a verifying key, proof and public
+//! signal in snarkjs' JSON export format, for a toy circuit whose only public
+//! signal is `public_inputs_digest` of `public_inputs.hex`. They were produced
+//! with arkworks' Groth16 prover (same curve and pairing check as snarkjs), so
+//! the test exercises the JSON → syscall encoding as well as the verifier.
+use std::path::PathBuf;
+
+use num_bigint::BigUint;
+use serde_json::Value;
+use solana_mixer::{
+    verifier::{negate_g1, public_inputs_digest, CircomGroth16, ProofVerifier},
+    CircomVerifyingKey, PublicInputs,
+};
+
+/// BN254 base field modulus q
+const FIELD_MODULUS: &str =
+    "21888242871839275222246405745257275088696311157297823662689037894645226208583";
+
+fn fixture(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures/circom_groth16")
+        .join(name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+}
+
+fn json(name: &str) -> Value {
+    serde_json::from_str(&fixture(name)).unwrap()
+}
+
+/// Decimal string to 32 bytes big endian
+fn be32(value: &Value) -> [u8; 32] {
+    let n: BigUint = value.as_str().unwrap().parse().unwrap();
+    let bytes = n.to_bytes_be();
+    let mut out = [0u8; 32];
+    out[32 - bytes.len()..].copy_from_slice(&bytes);
+    out
+}
+
+/// snarkjs `[x, y, "1"]` to x || y
+fn g1(point: &Value) -> [u8; 64] {
+    let mut out = [0u8; 64];
+    out[..32].copy_from_slice(&be32(&point[0]));
+    out[32..].copy_from_slice(&be32(&point[1]));
+    out
+}
+
+/// snarkjs `[[x.c0, x.c1], [y.c0, y.c1], ["1", "0"]]` to x.c1 || x.c0 || y.c1 || y.c0
+fn g2(point: &Value) -> [u8; 128] {
+    let mut out = [0u8; 128];
+    for (i, coordinate) in [&point[0][1], &point[0][0], &point[1][1], &point[1][0]]
+        .into_iter()
+        .enumerate()
+    {
+        out[32 * i..32 * (i + 1)].copy_from_slice(&be32(coordinate));
+    }
+    out
+}
+
+fn verifying_key() -> CircomVerifyingKey {
+    let vk = json("verification_key.json");
+    assert_eq!(vk["nPublic"], 1);
+    CircomVerifyingKey {
+        alpha_g1: g1(&vk["vk_alpha_1"]),
+        beta_g2: g2(&vk["vk_beta_2"]),
+        gamma_g2: g2(&vk["vk_gamma_2"]),
+        delta_g2: g2(&vk["vk_delta_2"]),
+        ic: [g1(&vk["IC"][0]), g1(&vk["IC"][1])],
+    }
+}
+
+fn proof() -> Vec<u8> {
+    let proof = json("proof.json");
+    [
+        &g1(&proof["pi_a"])[..],
+        &g2(&proof["pi_b"])[..],
+        &g1(&proof["pi_c"])[..],
+    ]
+    .concat()
+}
+
+fn public_inputs() -> Vec<u8> {
+    hex::decode(fixture("public_inputs.hex").trim()).unwrap()
+}
+
+#[test]
+fn test_public_inputs_digest_matches_the_public_signal() {
+    let public_inputs = public_inputs();
+    // a current withdrawal statement, not an arbitrary blob
+    PublicInputs::decode(&public_inputs).unwrap();
+    assert_eq!(
+        public_inputs_digest(&public_inputs),
+        be32(&json("public.json")[0])
+    );
+}
+
+#[test]
+fn test_negate_g1() {
+    let a = g1(&json("proof.json")["pi_a"]);
+    let negated = negate_g1(&a);
+    assert_eq!(negated[..32], a[..32]);
+    let q: BigUint = FIELD_MODULUS.parse().unwrap();
+    let y = BigUint::from_bytes_be(&a[32..]);
+    assert_eq!(BigUint::from_bytes_be(&negated[32..]), q - y);
+    assert_eq!(negate_g1(&negated), a);
+
+    assert_eq!(negate_g1(&[0u8; 64]), [0u8; 64]);
+}
+
+#[test]
+fn test_circom_groth16_accepts_the_snarkjs_proof() {
+    let vk = verifying_key();
+    CircomGroth16 { vk: &vk }
+        .verify(&proof(), &public_inputs(), &vk.hash())
+        .unwrap();
+}
+
+#[test]
+fn test_circom_groth16_rejects_tampering() {
+    let vk = verifying_key();
+    let verifier = CircomGroth16 { vk: &vk };
+    let (proof, public_inputs) = (proof(), public_inputs());
+
+    // C replaced by A: still a curve point, no longer a proof
+    let mut swapped = proof.clone();
+    swapped[192..256].copy_from_slice(&proof[0..64]);
+    assert!(verifier
+        .verify(&swapped, &public_inputs, &vk.hash())
+        .is_err());
+
+    // a flipped bit leaves the curve altogether
+    let mut flipped = proof.clone();
+    flipped[255] ^= 1;
+    assert!(verifier
+        .verify(&flipped, &public_inputs, &vk.hash())
+        .is_err());
+
+    // any other statement, here a larger fee
+    let mut inputs = PublicInputs::decode(&public_inputs).unwrap();
+    inputs.fee += 1;
+    assert!(verifier
+        .verify(&proof, &inputs.encode(), &vk.hash())
+        .is_err());
+
+    // a pool expecting another key
+    assert!(verifier.verify(&proof, &public_inputs, &[0u8; 32]).is_err());
+    // a truncated proof
+    assert!(verifier
+        .verify(&proof[..255], &public_inputs, &vk.hash())
+        .is_err());
+}
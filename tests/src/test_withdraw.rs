@@ -0,0 +1,434 @@
+//! `withdraw` from a SOL pool in LiteSVM
+use anchor_client::solana_sdk::{
+    instruction::AccountMeta, pubkey::Pubkey, signer::Signer, transaction::Transaction,
+};
+use litesvm::types::TransactionResult;
+use solana_mixer::{
+    domain_tag, hash_splits, merkle_tree::TreeRef, ErrorCode, Nullifier, PublicInputs,
+    WithdrawSplit, MAX_REFUND_ACCOUNT_SPACE,
+};
+
+use crate::{
+    note::{encrypt_note, recover_notes, Note, ViewingKey},
+    svm::{assert_error, commitment, ix, prove, Mixer, DEPOSIT_AMOUNT, GENESIS_HASH},
+    utils::{
+        deposit_events_from_logs, nullifier_address, split_accounts, tree_address,
+        withdraw_accounts,
+    },
+};
+
+#[test]
+fn test_proof_for_another_domain_is_rejected() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+
+    // same pool on another cluster
+    inputs.domain = domain_tag(&solana_mixer::ID, &mixer.pool, &[8u8; 32]);
+    assert_error(mixer.withdraw(&inputs), ErrorCode::DomainMismatch);
+    // another pool of this deployment
+    inputs.domain = domain_tag(&solana_mixer::ID, &Pubkey::new_unique(), &GENESIS_HASH);
+    assert_error(mixer.withdraw(&inputs), ErrorCode::DomainMismatch);
+    // another program
+    inputs.domain = domain_tag(&Pubkey::new_unique(), &mixer.pool, &GENESIS_HASH);
+    assert_error(mixer.withdraw(&inputs), ErrorCode::DomainMismatch);
+
+    inputs.domain = mixer.domain();
+    mixer.withdraw(&inputs).unwrap();
+}
+
+#[test]
+fn test_expired_proof_is_rejected() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    mixer.warp_to(1_700_000_000);
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+
+    inputs.expires_at = mixer.now() - 1;
+    assert_error(mixer.withdraw(&inputs), ErrorCode::ProofExpired);
+
+    // the deadline itself is still in time
+    inputs.expires_at = mixer.now();
+    mixer.withdraw(&inputs).unwrap();
+}
+
+#[test]
+fn test_refund_is_capped_at_the_nullifier_rent() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let max_refund = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(MAX_REFUND_ACCOUNT_SPACE);
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+
+    inputs.refund = max_refund + 1;
+    assert_error(mixer.withdraw(&inputs), ErrorCode::RefundTooLarge);
+
+    // the administrator pays the transaction fee, the caller only sees the refund
+    inputs.refund = max_refund;
+    let withdraw = mixer.withdraw_ix(&inputs);
+    let caller_before = mixer.lamports(&mixer.user.pubkey());
+    let (admin, user) = (mixer.admin.insecure_clone(), mixer.user.insecure_clone());
+    mixer.send(&[withdraw], &[&admin, &user]).unwrap();
+    assert_eq!(
+        mixer.lamports(&mixer.user.pubkey()),
+        caller_before + max_refund
+    );
+}
+
+#[test]
+fn test_sol_pool_pays_the_nullifier_rent_out_of_the_note() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let recipient = Pubkey::new_unique();
+    let inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+
+    let pool_before = mixer.lamports(&mixer.pool);
+    let caller_before = mixer.lamports(&mixer.user.pubkey());
+    let withdraw = mixer.withdraw_ix(&inputs);
+    let (admin, user) = (mixer.admin.insecure_clone(), mixer.user.insecure_clone());
+    mixer.send(&[withdraw], &[&admin, &user]).unwrap();
+
+    let rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Nullifier::SPACE);
+    let nullifier = nullifier_address(&mixer.pool, &inputs.nullifier_hash);
+    assert_eq!(mixer.lamports(&nullifier), rent);
+    assert_eq!(mixer.lamports(&mixer.pool), pool_before - DEPOSIT_AMOUNT);
+    assert_eq!(mixer.lamports(&recipient), DEPOSIT_AMOUNT - rent);
+    // the caller fronted nothing
+    assert_eq!(mixer.lamports(&mixer.user.pubkey()), caller_before);
+}
+
+/// The program's `is_spent` view, read from a simulated transaction
+fn is_spent(mixer: &Mixer, nullifier_hash: [u8; 32]) -> bool {
+    let view = ix(
+        solana_mixer::accounts::IsSpent {
+            state: mixer.pool,
+            nullifier: nullifier_address(&mixer.pool, &nullifier_hash),
+        },
+        solana_mixer::instruction::IsSpent {
+            _nullifier_hash: nullifier_hash,
+        },
+    );
+    let tx = Transaction::new_signed_with_payer(
+        &[view],
+        Some(&mixer.user.pubkey()),
+        &[&mixer.user],
+        mixer.svm.latest_blockhash(),
+    );
+    let simulated = mixer.svm.simulate_transaction(tx).unwrap();
+    match simulated.meta.return_data.data.as_slice() {
+        [spent] => *spent == 1,
+        data => panic!("unexpected return data {data:?}"),
+    }
+}
+
+#[test]
+fn test_double_withdraw_fails_with_nullifier_already_used() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    mixer.deposit(commitment(2)).unwrap();
+    let inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+
+    assert!(!is_spent(&mixer, inputs.nullifier_hash));
+    mixer.withdraw(&inputs).unwrap();
+    assert!(is_spent(&mixer, inputs.nullifier_hash));
+    assert!(!is_spent(&mixer, [2u8; 32]));
+
+    // the pool still holds another note, so only the nullifier stops this
+    assert_error(mixer.withdraw(&inputs), ErrorCode::NullifierAlreadyUsed);
+}
+
+#[test]
+fn test_partial_withdrawal_keeps_the_change_in_the_pool() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let pool_before = mixer.lamports(&mixer.pool);
+    let root_index_before = TreeRef::new(&mixer.tree_data(0))
+        .unwrap()
+        .header
+        .current_root_index;
+
+    let recipient = Pubkey::new_unique();
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+    inputs.amount = DEPOSIT_AMOUNT / 4;
+    inputs.change_commitment = commitment(2);
+    mixer.withdraw(&inputs).unwrap();
+
+    // only `amount` left the pool, the nullifier rent included
+    let rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Nullifier::SPACE);
+    assert_eq!(
+        mixer.lamports(&mixer.pool),
+        pool_before - DEPOSIT_AMOUNT / 4
+    );
+    assert_eq!(mixer.lamports(&recipient), DEPOSIT_AMOUNT / 4 - rent);
+
+    // the change note is the next leaf and its root the newest in the history
+    let data = mixer.tree_data(0);
+    let tree = TreeRef::new(&data).unwrap();
+    assert_eq!(tree.header.next_index, 2);
+    assert_eq!(tree.header.current_root_index, root_index_before + 1);
+    assert_eq!(
+        tree.root_history[tree.header.current_root_index as usize],
+        tree.header.current_root
+    );
+    assert_ne!(tree.header.current_root, inputs.root);
+
+    // and the change can be withdrawn against it
+    let recipient = Pubkey::new_unique();
+    let mut inputs = mixer.withdraw_inputs([2u8; 32], recipient, Pubkey::new_unique());
+    inputs.amount = DEPOSIT_AMOUNT - DEPOSIT_AMOUNT / 4;
+    mixer.withdraw(&inputs).unwrap();
+    assert_eq!(mixer.lamports(&mixer.pool), pool_before - DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_fresh_recipient_must_end_up_rent_exempt() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let nullifier_rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Nullifier::SPACE);
+    let wallet_rent = mixer.svm.minimum_balance_for_rent_exemption(0);
+
+    // a partial withdrawal leaving a new wallet one lamport short
+    let recipient = Pubkey::new_unique();
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+    inputs.amount = nullifier_rent + wallet_rent - 1;
+    inputs.change_commitment = commitment(2);
+    assert_error(mixer.withdraw(&inputs), ErrorCode::RecipientNotRentExempt);
+
+    // a split payee is held to the same minimum
+    let payee = Pubkey::new_unique();
+    let splits = [WithdrawSplit {
+        recipient: payee,
+        amount: wallet_rent - 1,
+    }];
+    let mut split_inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+    split_inputs.splits_hash = hash_splits(&splits);
+    assert_error(
+        withdraw_split(&mut mixer, &split_inputs, &splits, split_accounts(&splits)),
+        ErrorCode::RecipientNotRentExempt,
+    );
+
+    // exactly the minimum is enough
+    inputs.amount += 1;
+    mixer.withdraw(&inputs).unwrap();
+    assert_eq!(mixer.lamports(&recipient), wallet_rent);
+}
+
+/// `withdraw` of `inputs` announcing its change commitment with `change_note`
+fn withdraw_with_change_note(
+    mixer: &mut Mixer,
+    inputs: &PublicInputs,
+    change_note: Option<Vec<u8>>,
+) -> TransactionResult {
+    let public_inputs = inputs.encode();
+    let withdraw = ix(
+        solana_mixer::accounts::Withdraw {
+            current_tree: Some(tree_address(&mixer.pool, mixer.state().current_tree)),
+            ..withdraw_accounts(
+                mixer.pool,
+                inputs.tree_index,
+                mixer.user.pubkey(),
+                inputs.recipient,
+                inputs.relayer,
+                &inputs.nullifier_hash,
+            )
+        },
+        solana_mixer::instruction::Withdraw {
+            nullifier_bytes: inputs.nullifier_hash,
+            proof: prove(&public_inputs),
+            public_inputs,
+            change_note,
+            splits: vec![],
+        },
+    );
+    mixer.send_user(withdraw)
+}
+
+#[test]
+fn test_change_note_is_recovered_and_spent() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let pool_before = mixer.lamports(&mixer.pool);
+
+    let viewing_key = ViewingKey::generate();
+    let change = Note {
+        nullifier: [2u8; 32],
+        secret: [3u8; 32],
+        value: DEPOSIT_AMOUNT - DEPOSIT_AMOUNT / 4,
+    };
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    inputs.amount = DEPOSIT_AMOUNT / 4;
+    inputs.change_commitment = commitment(2);
+    let change_note = encrypt_note(&viewing_key.public(), &commitment(2), &change);
+    let meta = withdraw_with_change_note(&mut mixer, &inputs, Some(change_note)).unwrap();
+
+    // the wallet finds the change in the withdrawal's events, value included
+    let recovered = recover_notes(
+        deposit_events_from_logs(mixer.pool, &meta.logs),
+        &viewing_key,
+    );
+    assert_eq!(recovered.len(), 1);
+    assert_eq!(recovered[0].note, change);
+    assert_eq!(recovered[0].commitment, commitment(2));
+    assert_eq!((recovered[0].tree_index, recovered[0].leaf_index), (0, 1));
+
+    // and spends all of it; the mock verifier takes the nullifier as its hash
+    let recipient = Pubkey::new_unique();
+    let mut inputs =
+        mixer.withdraw_inputs(recovered[0].note.nullifier, recipient, Pubkey::new_unique());
+    inputs.amount = recovered[0].note.value;
+    mixer.withdraw(&inputs).unwrap();
+    let rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Nullifier::SPACE);
+    assert_eq!(mixer.lamports(&recipient), change.value - rent);
+    assert_eq!(mixer.lamports(&mixer.pool), pool_before - DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_change_note_without_a_change_commitment_is_rejected() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    let note = Note {
+        nullifier: [2u8; 32],
+        secret: [3u8; 32],
+        value: 0,
+    };
+    let change_note = encrypt_note(&ViewingKey::generate().public(), &[0u8; 32], &note);
+    assert_error(
+        withdraw_with_change_note(&mut mixer, &inputs, Some(change_note)),
+        ErrorCode::InvalidInput,
+    );
+    withdraw_with_change_note(&mut mixer, &inputs, None).unwrap();
+}
+
+#[test]
+fn test_amount_above_the_denomination_is_rejected() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    mixer.deposit(commitment(2)).unwrap();
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+    inputs.amount = DEPOSIT_AMOUNT + 1;
+    assert_error(mixer.withdraw(&inputs), ErrorCode::AmountTooLarge);
+}
+
+/// `withdraw` of `inputs` passing `splits` and `payees` as remaining accounts
+fn withdraw_split(
+    mixer: &mut Mixer,
+    inputs: &PublicInputs,
+    splits: &[WithdrawSplit],
+    payees: Vec<AccountMeta>,
+) -> TransactionResult {
+    let public_inputs = inputs.encode();
+    let mut withdraw = ix(
+        withdraw_accounts(
+            mixer.pool,
+            inputs.tree_index,
+            mixer.user.pubkey(),
+            inputs.recipient,
+            inputs.relayer,
+            &inputs.nullifier_hash,
+        ),
+        solana_mixer::instruction::Withdraw {
+            nullifier_bytes: inputs.nullifier_hash,
+            proof: prove(&public_inputs),
+            public_inputs,
+            change_note: None,
+            splits: splits.to_vec(),
+        },
+    );
+    withdraw.accounts.extend(payees);
+    mixer.send_user(withdraw)
+}
+
+#[test]
+fn test_splits_pay_each_payee() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let splits = [
+        WithdrawSplit {
+            recipient: Pubkey::new_unique(),
+            amount: DEPOSIT_AMOUNT / 5,
+        },
+        WithdrawSplit {
+            recipient: Pubkey::new_unique(),
+            amount: DEPOSIT_AMOUNT / 4,
+        },
+    ];
+    let recipient = Pubkey::new_unique();
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], recipient, Pubkey::new_unique());
+    inputs.splits_hash = hash_splits(&splits);
+    withdraw_split(&mut mixer, &inputs, &splits, split_accounts(&splits)).unwrap();
+
+    let rent = mixer
+        .svm
+        .minimum_balance_for_rent_exemption(Nullifier::SPACE);
+    assert_eq!(mixer.lamports(&splits[0].recipient), DEPOSIT_AMOUNT / 5);
+    assert_eq!(mixer.lamports(&splits[1].recipient), DEPOSIT_AMOUNT / 4);
+    assert_eq!(
+        mixer.lamports(&recipient),
+        DEPOSIT_AMOUNT - rent - DEPOSIT_AMOUNT / 5 - DEPOSIT_AMOUNT / 4
+    );
+}
+
+#[test]
+fn test_splits_must_match_the_proof_and_the_accounts() {
+    let mut mixer = Mixer::sol_pool(DEPOSIT_AMOUNT);
+    mixer.deposit(commitment(1)).unwrap();
+    let splits = [
+        WithdrawSplit {
+            recipient: Pubkey::new_unique(),
+            amount: DEPOSIT_AMOUNT / 5,
+        },
+        WithdrawSplit {
+            recipient: Pubkey::new_unique(),
+            amount: DEPOSIT_AMOUNT / 4,
+        },
+    ];
+    let mut inputs = mixer.withdraw_inputs([1u8; 32], Pubkey::new_unique(), Pubkey::new_unique());
+
+    // the proof commits to no splits, or to other ones
+    let payees = split_accounts(&splits);
+    assert_error(
+        withdraw_split(&mut mixer, &inputs, &splits, payees.clone()),
+        ErrorCode::InvalidSplits,
+    );
+    inputs.splits_hash = hash_splits(&splits[..1]);
+    assert_error(
+        withdraw_split(&mut mixer, &inputs, &splits, payees.clone()),
+        ErrorCode::InvalidSplits,
+    );
+
+    // a payee account missing, or out of order
+    inputs.splits_hash = hash_splits(&splits);
+    assert_error(
+        withdraw_split(&mut mixer, &inputs, &splits, payees[..1].to_vec()),
+        ErrorCode::InvalidSplits,
+    );
+    let swapped = vec![payees[1].clone(), payees[0].clone()];
+    assert_error(
+        withdraw_split(&mut mixer, &inputs, &splits, swapped),
+        ErrorCode::InvalidSplits,
+    );
+
+    // payees asking for more than the recipient's share
+    let greedy = [WithdrawSplit {
+        recipient: Pubkey::new_unique(),
+        amount: DEPOSIT_AMOUNT,
+    }];
+    inputs.splits_hash = hash_splits(&greedy);
+    assert_error(
+        withdraw_split(&mut mixer, &inputs, &greedy, split_accounts(&greedy)),
+        ErrorCode::InvalidSplits,
+    );
+
+    inputs.splits_hash = hash_splits(&splits);
+    withdraw_split(&mut mixer, &inputs, &splits, payees).unwrap();
+}
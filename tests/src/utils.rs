@@ -4,14 +4,17 @@ use anchor_client::{
         rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
         rpc_config::RpcTransactionConfig,
     },
-    solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature},
+    solana_sdk::{
+        commitment_config::CommitmentConfig, instruction::AccountMeta, pubkey, pubkey::Pubkey,
+        signature::Signature, system_program,
+    },
 };
 use ark_bn254::Fr;
 use base64::Engine;
 use light_poseidon::{Poseidon, PoseidonBytesHasher};
 use num_bigint::BigUint;
 use solana_transaction_status::{option_serializer::OptionSerializer, UiTransactionEncoding};
-use std::{error::Error, str::FromStr};
+use std::error::Error;
 
 pub fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
     let mut p = Poseidon::<Fr>::new_circom(2).unwrap();
@@ -20,34 +23,319 @@ pub fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
 
 pub const TREE_DEPTH: usize = 20;
 
+/// Address of the pool holding `deposit_amount` notes of `mint`
+/// (`solana_mixer::NATIVE_MINT` for SOL pools).
+pub fn pool_address(mint: &Pubkey, deposit_amount: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            solana_mixer::STATE_SEED,
+            mint.as_ref(),
+            &deposit_amount.to_le_bytes(),
+        ],
+        &solana_mixer::ID,
+    )
+    .0
+}
+
+/// Address of the nullifier account created when `nullifier_hash` is spent in `pool`.
+pub fn nullifier_address(pool: &Pubkey, nullifier_hash: &[u8]) -> Pubkey {
+    Pubkey::find_program_address(
+        &[solana_mixer::NULLIFIER_SEED, pool.as_ref(), nullifier_hash],
+        &solana_mixer::ID,
+    )
+    .0
+}
+
+/// Address and bump of the record a deposit of `commitment` creates in a pool
+/// with `unique_commitments` on; pass one address per commitment as remaining
+/// accounts and the bumps as `record_bump(s)`.
+pub fn commitment_record(pool: &Pubkey, commitment: &[u8; 32]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[solana_mixer::COMMITMENT_SEED, pool.as_ref(), commitment],
+        &solana_mixer::ID,
+    )
+}
+
+/// Address of the pool's protocol fee treasury: a program account for SOL pools,
+/// a token account for token pools.
+pub fn treasury_address(pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[solana_mixer::TREASURY_SEED, pool.as_ref()],
+        &solana_mixer::ID,
+    )
+    .0
+}
+
+/// Address of `relayer`'s registration with `pool`. Set it as `relayer_entry` of
+/// the withdraw accounts when the pool only pays registered relayers.
+pub fn relayer_entry_address(pool: &Pubkey, relayer: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[solana_mixer::RELAYER_SEED, pool.as_ref(), relayer.as_ref()],
+        &solana_mixer::ID,
+    )
+    .0
+}
+
+/// Address of tree `tree_index` of `pool`; tree 0 is created by `initialize`.
+pub fn tree_address(pool: &Pubkey, tree_index: u32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            solana_mixer::TREE_SEED,
+            pool.as_ref(),
+            &tree_index.to_le_bytes(),
+        ],
+        &solana_mixer::ID,
+    )
+    .0
+}
+
+/// Whether `nullifier_hash` has already been spent in `pool`, read straight from
+/// the nullifier account (same check as the program's `is_spent` view).
+pub fn is_spent(
+    rpc: &RpcClient,
+    pool: &Pubkey,
+    nullifier_hash: &[u8],
+) -> Result<bool, Box<dyn Error>> {
+    let account = rpc
+        .get_account_with_commitment(
+            &nullifier_address(pool, nullifier_hash),
+            CommitmentConfig::confirmed(),
+        )?
+        .value;
+    Ok(account.is_some_and(|a| a.owner == solana_mixer::ID))
+}
+
+/// Token account holding the funds of a token pool.
+pub fn vault_address(pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[solana_mixer::VAULT_SEED, pool.as_ref()],
+        &solana_mixer::ID,
+    )
+    .0
+}
+
+/// Token accounts needed to deposit into / withdraw from a token pool
+pub struct TokenPoolAccounts {
+    pub mint: Pubkey,
+    pub token_program: Pubkey,
+    /// depositor's account on deposit, recipient's account on withdraw
+    pub user_token_account: Pubkey,
+    /// only read on withdraw
+    pub relayer_token_account: Option<Pubkey>,
+}
+
+/// Deposit accounts for a SOL pool; `tree_index` is the pool's `current_tree`.
+pub fn deposit_accounts(
+    pool: Pubkey,
+    tree_index: u32,
+    depositor: Pubkey,
+) -> solana_mixer::accounts::Deposit {
+    solana_mixer::accounts::Deposit {
+        state: pool,
+        tree: tree_address(&pool, tree_index),
+        depositor,
+        mint: None,
+        vault: None,
+        depositor_token_account: None,
+        token_program: None,
+        system_program: system_program::ID,
+    }
+}
+
+/// Deposit accounts for a token pool.
+pub fn token_deposit_accounts(
+    pool: Pubkey,
+    tree_index: u32,
+    depositor: Pubkey,
+    token: &TokenPoolAccounts,
+) -> solana_mixer::accounts::Deposit {
+    solana_mixer::accounts::Deposit {
+        mint: Some(token.mint),
+        vault: Some(vault_address(&pool)),
+        depositor_token_account: Some(token.user_token_account),
+        token_program: Some(token.token_program),
+        ..deposit_accounts(pool, tree_index, depositor)
+    }
+}
+
+/// Withdraw accounts for a SOL pool; `tree_index` is the one in the public inputs.
+pub fn withdraw_accounts(
+    pool: Pubkey,
+    tree_index: u32,
+    caller: Pubkey,
+    recipient: Pubkey,
+    relayer: Pubkey,
+    nullifier_hash: &[u8],
+) -> solana_mixer::accounts::Withdraw {
+    solana_mixer::accounts::Withdraw {
+        state: pool,
+        tree: tree_address(&pool, tree_index),
+        current_tree: None,
+        nullifier: nullifier_address(&pool, nullifier_hash),
+        caller,
+        recipient,
+        relayer,
+        verifying_key: None,
+        mint: None,
+        vault: None,
+        recipient_token_account: None,
+        recipient_ata: None,
+        relayer_token_account: None,
+        relayer_entry: None,
+        treasury: Some(treasury_address(&pool)),
+        treasury_token_account: None,
+        associated_token_program: None,
+        token_program: None,
+        system_program: system_program::ID,
+    }
+}
+
+/// Accounts of a `private_transfer` spending `nullifier_hash` with a root of
+/// tree `tree_index` and inserting into `current_tree`
+pub fn private_transfer_accounts(
+    pool: Pubkey,
+    tree_index: u32,
+    current_tree: u32,
+    caller: Pubkey,
+    nullifier_hash: &[u8],
+) -> solana_mixer::accounts::PrivateTransfer {
+    solana_mixer::accounts::PrivateTransfer {
+        state: pool,
+        tree: tree_address(&pool, tree_index),
+        current_tree: tree_address(&pool, current_tree),
+        nullifier: nullifier_address(&pool, nullifier_hash),
+        caller,
+        verifying_key: None,
+        system_program: system_program::ID,
+    }
+}
+
+/// Accounts of a SOL shielded pool `transact`; `tree_index` is the tree of the
+/// proof's root, `current_tree` the one receiving the outputs
+pub fn transact_accounts(
+    pool: Pubkey,
+    tree_index: u32,
+    current_tree: u32,
+    caller: Pubkey,
+    recipient: Pubkey,
+    relayer: Pubkey,
+    input_nullifiers: &[[u8; 32]; 2],
+) -> solana_mixer::accounts::Transact {
+    solana_mixer::accounts::Transact {
+        state: pool,
+        tree: tree_address(&pool, tree_index),
+        current_tree: tree_address(&pool, current_tree),
+        nullifier_0: nullifier_address(&pool, &input_nullifiers[0]),
+        nullifier_1: nullifier_address(&pool, &input_nullifiers[1]),
+        caller,
+        recipient,
+        relayer,
+        relayer_entry: None,
+        verifying_key: None,
+        mint: None,
+        vault: None,
+        caller_token_account: None,
+        recipient_token_account: None,
+        relayer_token_account: None,
+        treasury: Some(treasury_address(&pool)),
+        treasury_token_account: None,
+        token_program: None,
+        system_program: system_program::ID,
+    }
+}
+
+/// Remaining accounts of a SOL pool withdrawal paying `splits`, in order.
+/// Token pools pass each payee's token account instead.
+pub fn split_accounts(splits: &[solana_mixer::WithdrawSplit]) -> Vec<AccountMeta> {
+    splits
+        .iter()
+        .map(|split| AccountMeta::new(split.recipient, false))
+        .collect()
+}
+
+/// Withdraw accounts for a token pool.
+pub fn token_withdraw_accounts(
+    pool: Pubkey,
+    tree_index: u32,
+    caller: Pubkey,
+    recipient: Pubkey,
+    relayer: Pubkey,
+    nullifier_hash: &[u8],
+    token: &TokenPoolAccounts,
+) -> solana_mixer::accounts::Withdraw {
+    solana_mixer::accounts::Withdraw {
+        mint: Some(token.mint),
+        vault: Some(vault_address(&pool)),
+        recipient_token_account: Some(token.user_token_account),
+        relayer_token_account: token.relayer_token_account,
+        treasury: None,
+        treasury_token_account: Some(treasury_address(&pool)),
+        token_program: Some(token.token_program),
+        ..withdraw_accounts(pool, tree_index, caller, recipient, relayer, nullifier_hash)
+    }
+}
+
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey =
+    pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+/// Associated token account of `owner` for `mint`
+pub fn associated_token_address(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[owner.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Withdraw accounts for a token pool paying a recipient that may have no
+/// token account yet: `withdraw` creates its associated token account, the
+/// caller paying the rent. `token.user_token_account` is ignored.
+pub fn token_withdraw_to_new_account_accounts(
+    pool: Pubkey,
+    tree_index: u32,
+    caller: Pubkey,
+    recipient: Pubkey,
+    relayer: Pubkey,
+    nullifier_hash: &[u8],
+    token: &TokenPoolAccounts,
+) -> solana_mixer::accounts::Withdraw {
+    solana_mixer::accounts::Withdraw {
+        recipient_token_account: None,
+        recipient_ata: Some(associated_token_address(
+            &recipient,
+            &token.mint,
+            &token.token_program,
+        )),
+        associated_token_program: Some(ASSOCIATED_TOKEN_PROGRAM_ID),
+        ..token_withdraw_accounts(
+            pool,
+            tree_index,
+            caller,
+            recipient,
+            relayer,
+            nullifier_hash,
+            token,
+        )
+    }
+}
+
 #[derive(Debug)]
 struct LeafEntry {
     index: usize,
     commitment: [u8; 32],
 }
-/// Fetch all DepositEvent commits from the mixer program, return:
-///  (all_commitments, (index, commitment), all_leaf_indices, my_leaf_index_or_zero, did_I_find_my_commitment)
-pub fn fetch_deposits(
-    commitment_to_find: [u8; 32],
-) -> Result<
-    (
-        Vec<[u8; 32]>,
-        Vec<(usize, [u8; 32])>,
-        Vec<usize>,
-        usize,
-        bool,
-    ),
-    Box<dyn Error>,
-> {
+
+/// Every `DepositEvent` emitted for `pool`, read from the logs of the pool's transactions
+pub fn fetch_deposit_events(
+    pool: Pubkey,
+) -> Result<Vec<solana_mixer::DepositEvent>, Box<dyn Error>> {
     let rpc = RpcClient::new_with_commitment(
         "http://127.0.0.1:8899".to_string(),
         CommitmentConfig::confirmed(),
     );
 
-    let program_id = Pubkey::from_str("AQW933TrdFxE5q7982Vb57crHjZe3B7EZaHotdXnaQYQ")?;
-
     let sigs = rpc.get_signatures_for_address_with_config(
-        &program_id,
+        &pool,
         GetConfirmedSignaturesForAddress2Config {
             before: None,
             until: None,
@@ -56,14 +344,7 @@ pub fn fetch_deposits(
         },
     )?;
 
-    let mut commitments = Vec::with_capacity(sigs.len());
-    let mut leaf_indices = Vec::with_capacity(sigs.len());
-    let mut my_index: Option<usize> = None;
-    let mut leaf_entries: Vec<LeafEntry> = Vec::new();
-
-    const PREFIX: &str = "Program data: ";
-    let prefix_len = PREFIX.len();
-
+    let mut events = Vec::with_capacity(sigs.len());
     for sig_info in sigs {
         let sig: Signature = sig_info.signature.parse()?;
 
@@ -77,33 +358,67 @@ pub fn fetch_deposits(
         )?;
 
         if let Some(OptionSerializer::Some(logs)) = tx.transaction.meta.map(|m| m.log_messages) {
-            for log in logs.iter().filter(|l| l.starts_with(PREFIX)) {
-                let b64 = &log[prefix_len..];
-                if let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(b64) {
-                    if bytes.len() >= 8 {
-                        let (disc, data) = bytes.split_at(8);
-
-                        if disc == solana_mixer::DepositEvent::DISCRIMINATOR {
-                            if let Ok(event) = solana_mixer::DepositEvent::try_from_slice(data) {
-                                let idx = event.leaf_index as usize;
-                                leaf_entries.push(LeafEntry {
-                                    index: idx,
-                                    commitment: event.commitment,
-                                });
-                                if event.commitment == commitment_to_find {
-                                    my_index = Some(idx);
-                                }
-                            }
-                        }
-                    }
-                }
+            events.extend(deposit_events_from_logs(pool, &logs));
+        }
+    }
+    Ok(events)
+}
+
+/// The `DepositEvent`s of `pool` in one transaction's logs
+pub fn deposit_events_from_logs(pool: Pubkey, logs: &[String]) -> Vec<solana_mixer::DepositEvent> {
+    const PREFIX: &str = "Program data: ";
+    logs.iter()
+        .filter_map(|log| log.strip_prefix(PREFIX))
+        .filter_map(|b64| base64::engine::general_purpose::STANDARD.decode(b64).ok())
+        .filter(|bytes| bytes.len() >= 8)
+        .filter_map(|bytes| {
+            let (disc, data) = bytes.split_at(8);
+            if disc != solana_mixer::DepositEvent::DISCRIMINATOR {
+                return None;
             }
+            solana_mixer::DepositEvent::try_from_slice(data).ok()
+        })
+        .filter(|event| event.pool == pool)
+        .collect()
+}
+
+/// Fetch all DepositEvent commits made into tree `tree_index` of `pool`, return:
+///  (all_commitments, (index, commitment), all_leaf_indices, my_leaf_index_or_zero, did_I_find_my_commitment)
+pub fn fetch_deposits(
+    pool: Pubkey,
+    tree_index: u32,
+    commitment_to_find: [u8; 32],
+) -> Result<
+    (
+        Vec<[u8; 32]>,
+        Vec<(usize, [u8; 32])>,
+        Vec<usize>,
+        usize,
+        bool,
+    ),
+    Box<dyn Error>,
+> {
+    let mut my_index: Option<usize> = None;
+    let mut leaf_entries: Vec<LeafEntry> = Vec::new();
+
+    for event in fetch_deposit_events(pool)? {
+        if event.tree_index != tree_index {
+            continue;
+        }
+        let idx = event.leaf_index as usize;
+        leaf_entries.push(LeafEntry {
+            index: idx,
+            commitment: event.commitment,
+        });
+        if event.commitment == commitment_to_find {
+            my_index = Some(idx);
         }
     }
 
     leaf_entries.sort_unstable_by_key(|e| e.index);
 
-    (leaf_indices, commitments) = leaf_entries.iter().map(|e| (e.index, e.commitment)).unzip();
+    let (leaf_indices, commitments): (Vec<usize>, Vec<[u8; 32]>) =
+        leaf_entries.iter().map(|e| (e.index, e.commitment)).unzip();
 
     let found = my_index.is_some();
     let index = my_index.unwrap_or(0);